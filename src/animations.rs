@@ -1,26 +1,567 @@
-#[derive(Debug, Clone, PartialEq)]
+use crate::{
+    Json, Xid,
+    engine::Engine,
+    wit::{
+        traits::GuestAnimation,
+        types::InstanceId
+    }
+};
+
+#[derive(Debug, Default, Clone, PartialEq)]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub enum Timing {
+    #[default]
     Linear,
+    EaseIn,
+    EaseOut,
     EaseInOut,
+    CubicBezier(f32, f32, f32, f32),
+    Steps(u32, Jump),
+    Spring {
+        stiffness: f32,
+        damping: f32,
+        mass: f32
+    }
 }
 
+// which ends of a `Steps` timing get a step of their own, as in CSS
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Jump {
+    Start,
+    #[default]
+    End,
+    None,
+    Both
+}
+
+impl Timing {
+    // maps linear progress through a segment onto eased progress, both from 0 to 1
+    pub(crate) fn ease(&self, progress: f32) -> f32 {
+        let progress = progress.clamp(0.0, 1.0);
+        
+        // with the control points CSS uses
+        let (x1, y1, x2, y2) = match self {
+            Timing::Linear => return progress,
+            Timing::EaseIn => (0.42, 0.0, 1.0, 1.0),
+            Timing::EaseOut => (0.0, 0.0, 0.58, 1.0),
+            Timing::EaseInOut => (0.42, 0.0, 0.58, 1.0),
+            Timing::CubicBezier(x1, y1, x2, y2) => (x1.clamp(0.0, 1.0), *y1, x2.clamp(0.0, 1.0), *y2),
+            Timing::Steps(steps, jump) => return Timing::step(progress, *steps, *jump),
+            Timing::Spring { stiffness, damping, mass } => return Timing::spring(progress, *stiffness, *damping, *mass)
+        };
+        
+        let curve = lyon::geom::CubicBezierSegment {
+            from: lyon::math::point(0.0, 0.0),
+            ctrl1: lyon::math::point(x1, y1),
+            ctrl2: lyon::math::point(x2, y2),
+            to: lyon::math::point(1.0, 1.0)
+        };
+        
+        // the curve's x is the progress, so it's the `t` that gives that x which the value's read at, not the progress itself;
+        // with both x control points kept within 0 to 1 there's exactly one
+        let t = curve.solve_t_for_x(progress).first().copied().unwrap_or(progress);
+        curve.y(t)
+    }
+    
+    fn step(progress: f32, steps: u32, jump: Jump) -> f32 {
+        let steps = steps.max(1) as f32;
+        let jumps = match jump {
+            Jump::Start | Jump::End => steps,
+            Jump::None => (steps - 1.0).max(1.0),
+            Jump::Both => steps + 1.0
+        };
+        
+        let mut step = (progress * steps).floor();
+        if matches!(jump, Jump::Start | Jump::Both) {
+            step += 1.0;
+        }
+        
+        (step / jumps).clamp(0.0, 1.0)
+    }
+    
+    // a damped spring let go from 0 towards 1, with the progress stretched over however long it takes to settle
+    fn spring(progress: f32, stiffness: f32, damping: f32, mass: f32) -> f32 {
+        if (progress >= 1.0) || (stiffness <= 0.0) || (mass <= 0.0) {
+            return 1.0;
+        }
+        
+        let w0 = (stiffness / mass).sqrt();
+        
+        // with little or no damping it'd swing for as good as ever, and any sample would land at some arbitrary point in a swing,
+        // so it's given at least enough to settle within 20 of them
+        let least = 1000f32.ln() / (40.0 * std::f32::consts::PI);
+        let zeta = (damping / (2.0 * (stiffness * mass).sqrt())).max(least);
+        
+        // until it's within 0.1% of where it's going
+        let decay = if zeta < 1.0 { zeta * w0 } else { w0 * (zeta - ((zeta * zeta) - 1.0).sqrt()) };
+        let settle = 1000f32.ln() / decay;
+        let t = progress * settle;
+        
+        if zeta < 1.0 {
+            let wd = w0 * (1.0 - (zeta * zeta)).sqrt();
+            1.0 - ((-zeta * w0 * t).exp() * ((wd * t).cos() + (((zeta * w0) / wd) * (wd * t).sin())))
+        } else if zeta == 1.0 {
+            1.0 - ((-w0 * t).exp() * (1.0 + (w0 * t)))
+        } else {
+            let root = ((zeta * zeta) - 1.0).sqrt();
+            let (r1, r2) = (-w0 * (zeta - root), -w0 * (zeta + root));
+            1.0 + (((r2 * (r1 * t).exp()) - (r1 * (r2 * t).exp())) / (r1 - r2))
+        }
+    }
+}
+
+// a stop along the way, eased towards the next one with its own timing
+#[derive(Debug, Clone, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Keyframe {
+    pub offset: f32,
+    pub value: f32,
+    #[serde(default)]
+    pub timing: Timing
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Iterations {
+    Count(f32),
+    Infinite
+}
+impl Default for Iterations {
+    fn default() -> Self {
+        Iterations::Count(1.0)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Direction {
+    #[default]
+    Normal,
+    Reverse,
+    Alternate,
+    AlternateReverse
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Fill {
+    #[default]
+    None,
+    Forwards,
+    Backwards,
+    Both
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Stage {
+    Before,
+    Active,
+    After
+}
+
+pub(crate) struct Sample {
+    pub(crate) values: Vec<(String, Json)>,
+    pub(crate) stage: Stage,
+    pub(crate) iteration: u32,
+    pub(crate) finished: bool
+}
+
+// everything but the keyframes can be left out when it's described in JSON
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 pub struct Animation {
-    pub curve: lyon::geom::CubicBezierSegment<f32>,
+    // the stops for each property, by offset
+    pub keyframes: std::collections::HashMap<String, Vec<Keyframe>>,
     pub duration: f32,
-    pub began: std::time::Instant,
-    pub attribute: String,
+    pub delay: f32,
+    pub iterations: Iterations,
+    pub direction: Direction,
+    pub fill: Fill,
+    
+    #[serde(skip)]
+    pub(crate) id: Xid,
+    // the animation's own time in seconds, as of `synced` on the engine's clock
+    #[serde(skip)]
+    pub(crate) time: f32,
+    #[serde(skip)]
+    pub(crate) synced: u128,
+    #[serde(skip)]
+    pub(crate) rate: f32,
+    #[serde(skip)]
+    pub(crate) paused: bool,
+    #[serde(skip)]
+    pub(crate) on_finish: Option<String>,
+    // what the properties were before the animation touched them, for when it doesn't fill
+    #[serde(skip)]
+    pub(crate) base: std::collections::HashMap<String, Json>,
+    #[serde(skip)]
+    pub(crate) started: bool,
+    #[serde(skip)]
+    pub(crate) iteration: u32
+}
+impl Default for Animation {
+    fn default() -> Self {
+        Animation::new(Default::default(), 0.0)
+    }
 }
 
 impl Animation {
-    fn tick(&self) -> (f32, f32) {
-        let mut progress = std::time::Instant::now()
-            .duration_since(self.began)
-            .as_secs_f32()
-            / self.duration;
-        progress = progress.min(1.0);
-        let nxt_val = self.curve.sample(progress).y;
-        (nxt_val, progress)
+    pub fn new(keyframes: std::collections::HashMap<String, Vec<Keyframe>>, duration: f32) -> Animation {
+        let mut keyframes = keyframes;
+        for stops in keyframes.values_mut() {
+            stops.sort_by(|a, b| a.offset.total_cmp(&b.offset));
+        }
+        
+        Animation {
+            keyframes,
+            duration,
+            delay: 0.0,
+            iterations: Iterations::default(),
+            direction: Direction::default(),
+            fill: Fill::default(),
+            id: 0,
+            time: 0.0,
+            synced: 0,
+            rate: 1.0,
+            paused: false,
+            on_finish: None,
+            base: Default::default(),
+            started: false,
+            iteration: 0
+        }
+    }
+    
+    // a single property going from one value to another, left at the new one once it's done
+    pub(crate) fn transition(property: &str, from: f32, to: f32, duration: f32, timing: Timing, began: u128) -> Animation {
+        let stops = vec![
+            Keyframe { offset: 0.0, value: from, timing },
+            Keyframe { offset: 1.0, value: to, timing: Timing::Linear }
+        ];
+        
+        Animation {
+            fill: Fill::Forwards,
+            synced: began,
+            ..Animation::new(std::iter::once((property.to_string(), stops)).collect(), duration)
+        }
+    }
+    
+    pub(crate) fn current_time(&self, now: u128) -> f32 {
+        if self.paused {
+            self.time
+        } else {
+            self.time + ((((now as f64) - (self.synced as f64)) / 1000.0) as f32 * self.rate)
+        }
+    }
+    
+    // brings `time` up to date, so the rate or time can change from here on without affecting what's already played
+    pub(crate) fn sync(&mut self, now: u128) {
+        self.time = self.current_time(now);
+        self.synced = now;
+    }
+    
+    pub(crate) fn tick(&self, now: u128) -> Sample {
+        let time = self.current_time(now);
+        let elapsed = time - self.delay;
+        let count = match self.iterations {
+            Iterations::Count(n) => n.max(0.0),
+            Iterations::Infinite => f32::INFINITY
+        };
+        
+        let (stage, iteration, progress) = if elapsed < 0.0 {
+            (Stage::Before, 0, 0.0)
+        } else if (self.duration <= 0.0) || (elapsed >= (self.duration * count)) {
+            // finishes wherever the last iteration did, part of the way through for a fractional count
+            let iteration = if count.is_finite() { (count.ceil() as u32).saturating_sub(1) } else { 0 };
+            let progress = if count.is_finite() && (count.fract() > 0.0) { count.fract() } else { 1.0 };
+            (Stage::After, iteration, progress)
+        } else {
+            let iterations = elapsed / self.duration;
+            (Stage::Active, iterations.floor() as u32, iterations.fract())
+        };
+        
+        let reversed = match self.direction {
+            Direction::Normal => false,
+            Direction::Reverse => true,
+            Direction::Alternate => (iteration % 2) == 1,
+            Direction::AlternateReverse => (iteration % 2) == 0
+        };
+        let progress = if reversed { 1.0 - progress } else { progress };
+        
+        let fills = match stage {
+            Stage::Before => matches!(self.fill, Fill::Backwards | Fill::Both),
+            Stage::Active => true,
+            Stage::After => matches!(self.fill, Fill::Forwards | Fill::Both)
+        };
+        
+        let values =
+            self
+                .keyframes
+                .iter()
+                .filter_map(|(property, stops)| {
+                    let value = if fills {
+                        Animation::value(stops, progress).map(Json::from)
+                    } else {
+                        self.base.get(property).cloned()
+                    };
+                    value.map(|value| (property.clone(), value))
+                })
+                .collect();
+        
+        // played backwards, it's done once it's back at the start
+        let finished = if self.rate < 0.0 { time <= 0.0 } else { stage == Stage::After };
+        
+        Sample { values, stage, iteration, finished }
+    }
+    
+    fn value(stops: &[Keyframe], progress: f32) -> Option<f32> {
+        match stops.iter().position(|stop| stop.offset > progress) {
+            None => stops.last().map(|stop| stop.value),
+            Some(0) => Some(stops[0].value),
+            Some(idx) => {
+                let (a, b) = (&stops[idx - 1], &stops[idx]);
+                let local = (progress - a.offset) / (b.offset - a.offset);
+                Some(a.value + ((b.value - a.value) * a.timing.ease(local)))
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, Hash, Eq, PartialEq)]
+pub struct AnimationX {
+    pub(crate) id: Xid,
+    pub(crate) instance_id: InstanceId
+}
+
+impl GuestAnimation for AnimationX {
+    fn pause(&self) {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| {
+            animation.sync(now);
+            animation.paused = true;
+        });
+    }
+    
+    fn resume(&self) {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| {
+            animation.sync(now);
+            animation.paused = false;
+        });
+    }
+    
+    fn seek(&self, time: f32) {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| {
+            animation.time = time;
+            animation.synced = now;
+            // so skipping ahead doesn't look like a repeat
+            animation.iteration = animation.tick(now).iteration;
+        });
+    }
+    
+    fn reverse(&self) {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| {
+            animation.sync(now);
+            animation.rate = -animation.rate;
+        });
+    }
+    
+    fn cancel(&self) {
+        Engine::cancel_animation(self.instance_id, self.id);
+    }
+    
+    fn playback_rate(&self) -> f32 {
+        Engine::with_animation(self.instance_id, self.id, |animation, _| animation.rate).unwrap_or_default()
+    }
+    
+    fn set_playback_rate(&self, rate: f32) {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| {
+            animation.sync(now);
+            animation.rate = rate;
+        });
+    }
+    
+    fn current_time(&self) -> Option<f32> {
+        Engine::with_animation(self.instance_id, self.id, |animation, now| animation.current_time(now))
+    }
+    
+    fn on_finish(&self, handler: String) {
+        Engine::on_finish(self.instance_id, self.id, handler);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // 0 to 100 over 2s, twice, after a 1s delay, with -1 as what was there beforehand
+    fn animation(direction: Direction, fill: Fill) -> Animation {
+        let stops = vec![
+            Keyframe { offset: 0.0, value: 0.0, timing: Timing::Linear },
+            Keyframe { offset: 1.0, value: 100.0, timing: Timing::Linear }
+        ];
+        
+        Animation {
+            delay: 1.0,
+            iterations: Iterations::Count(2.0),
+            direction,
+            fill,
+            base: std::iter::once(("x".to_string(), Json::from(-1.0))).collect(),
+            ..Animation::new(std::iter::once(("x".to_string(), stops)).collect(), 2.0)
+        }
+    }
+    
+    fn value(animation: &Animation, now: u128) -> f32 {
+        let sample = animation.tick(now);
+        sample.values.iter().find(|(property, _)| property == "x").map(|(_, value)| value.as_f64()).flatten().unwrap() as f32
+    }
+    
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{a} != {b}");
+    }
+    
+    #[test]
+    fn direction() {
+        assert_near(value(&animation(Direction::Normal, Fill::None), 1500), 25.0);
+        assert_near(value(&animation(Direction::Normal, Fill::None), 3500), 25.0);
+        
+        assert_near(value(&animation(Direction::Reverse, Fill::None), 1500), 75.0);
+        assert_near(value(&animation(Direction::Reverse, Fill::None), 3500), 75.0);
+        
+        assert_near(value(&animation(Direction::Alternate, Fill::None), 1500), 25.0);
+        assert_near(value(&animation(Direction::Alternate, Fill::None), 3500), 75.0);
+        
+        assert_near(value(&animation(Direction::AlternateReverse, Fill::None), 1500), 75.0);
+        assert_near(value(&animation(Direction::AlternateReverse, Fill::None), 3500), 25.0);
+    }
+    
+    #[test]
+    fn fill() {
+        // before the delay's up
+        assert_near(value(&animation(Direction::Normal, Fill::None), 500), -1.0);
+        assert_near(value(&animation(Direction::Normal, Fill::Forwards), 500), -1.0);
+        assert_near(value(&animation(Direction::Normal, Fill::Backwards), 500), 0.0);
+        assert_near(value(&animation(Direction::Reverse, Fill::Both), 500), 100.0);
+        
+        // once it's over
+        assert_near(value(&animation(Direction::Normal, Fill::None), 6000), -1.0);
+        assert_near(value(&animation(Direction::Normal, Fill::Backwards), 6000), -1.0);
+        assert_near(value(&animation(Direction::Normal, Fill::Forwards), 6000), 100.0);
+        // the second iteration ran backwards
+        assert_near(value(&animation(Direction::Alternate, Fill::Both), 6000), 0.0);
+    }
+    
+    #[test]
+    fn iterations() {
+        let normal = animation(Direction::Normal, Fill::Forwards);
+        assert!(normal.tick(500).stage == Stage::Before);
+        assert_eq!(normal.tick(3500).iteration, 1);
+        assert!(!normal.tick(4900).finished);
+        assert!(normal.tick(5000).finished);
+        
+        // stops part of the way through the last one
+        let fractional = Animation { iterations: Iterations::Count(1.5), ..animation(Direction::Normal, Fill::Forwards) };
+        assert_near(value(&fractional, 10_000), 50.0);
+        
+        let infinite = Animation { iterations: Iterations::Infinite, ..animation(Direction::Normal, Fill::None) };
+        assert!(!infinite.tick(1_000_000).finished);
+        assert_near(value(&infinite, 1_001_500), 25.0);
+    }
+    
+    #[test]
+    fn keyframes() {
+        let stops = vec![
+            Keyframe { offset: 1.0, value: 0.0, timing: Timing::Linear },
+            Keyframe { offset: 0.0, value: 0.0, timing: Timing::Linear },
+            Keyframe { offset: 0.5, value: 100.0, timing: Timing::Linear }
+        ];
+        let animation = Animation::new(std::iter::once(("x".to_string(), stops)).collect(), 1.0);
+        
+        // the stops are sorted, whatever order they're given in
+        assert_near(value(&animation, 250), 50.0);
+        assert_near(value(&animation, 500), 100.0);
+        assert_near(value(&animation, 750), 50.0);
+    }
+    
+    #[test]
+    fn bezier() {
+        for timing in [Timing::Linear, Timing::EaseIn, Timing::EaseOut, Timing::EaseInOut, Timing::CubicBezier(0.1, 0.7, 1.0, 0.1)] {
+            assert_near(timing.ease(0.0), 0.0);
+            assert_near(timing.ease(1.0), 1.0);
+        }
+        
+        // read off at the `t` that gives the progress as x, which for these control points is the progress itself
+        assert_near(Timing::CubicBezier(0.0, 0.0, 1.0, 1.0).ease(0.3), 0.3);
+        assert_near(Timing::CubicBezier(1.0 / 3.0, 0.0, 2.0 / 3.0, 1.0).ease(0.3), 0.216);
+        
+        assert_near(Timing::EaseIn.ease(0.5), 0.315);
+        assert_near(Timing::EaseOut.ease(0.5), 0.685);
+        assert_near(Timing::EaseInOut.ease(0.5), 0.5);
+        
+        // y isn't clamped, so it can overshoot
+        assert!(Timing::CubicBezier(0.3, 1.5, 0.7, 1.5).ease(0.5) > 1.0);
+    }
+    
+    #[test]
+    fn steps() {
+        let ease = |jump: Jump, progress: f32| Timing::Steps(4, jump).ease(progress);
+        
+        assert_near(ease(Jump::End, 0.0), 0.0);
+        assert_near(ease(Jump::End, 0.3), 0.25);
+        assert_near(ease(Jump::End, 1.0), 1.0);
+        
+        assert_near(ease(Jump::Start, 0.0), 0.25);
+        assert_near(ease(Jump::Start, 0.3), 0.5);
+        assert_near(ease(Jump::Start, 1.0), 1.0);
+        
+        assert_near(ease(Jump::None, 0.0), 0.0);
+        assert_near(ease(Jump::None, 0.3), 1.0 / 3.0);
+        assert_near(ease(Jump::None, 1.0), 1.0);
+        
+        assert_near(ease(Jump::Both, 0.0), 0.2);
+        assert_near(ease(Jump::Both, 0.3), 0.4);
+        assert_near(ease(Jump::Both, 1.0), 1.0);
+    }
+    
+    #[test]
+    fn spring() {
+        let bouncy = Timing::Spring { stiffness: 100.0, damping: 5.0, mass: 1.0 };
+        let critical = Timing::Spring { stiffness: 100.0, damping: 20.0, mass: 1.0 };
+        let overdamped = Timing::Spring { stiffness: 100.0, damping: 40.0, mass: 1.0 };
+        
+        for timing in [&bouncy, &critical, &overdamped] {
+            assert_near(timing.ease(0.0), 0.0);
+            assert_near(timing.ease(1.0), 1.0);
+            // it's settled by the end, not just snapped there
+            assert_near(timing.ease(0.999), 1.0);
+        }
+        
+        let samples = |timing: &Timing| (0..=100).map(|n| timing.ease(n as f32 / 100.0)).collect::<Vec<_>>();
+        assert!(samples(&bouncy).iter().any(|value| *value > 1.01));
+        assert!(samples(&critical).windows(2).all(|pair| pair[1] >= pair[0] - f32::EPSILON));
+        assert!(samples(&overdamped).iter().all(|value| *value <= 1.0 + f32::EPSILON));
+        
+        // without any damping it still settles, rather than stopping wherever it happens to be in a swing
+        let undamped = Timing::Spring { stiffness: 100.0, damping: 0.0, mass: 1.0 };
+        assert_near(undamped.ease(0.0), 0.0);
+        assert_near(undamped.ease(0.999), 1.0);
+        assert_near(undamped.ease(1.0), 1.0);
+        assert!(samples(&undamped).iter().any(|value| *value > 1.5));
+        assert!(samples(&undamped)[90..].iter().all(|value| (value - 1.0).abs() < 0.01));
+        assert_near(Timing::Spring { stiffness: 100.0, damping: -1.0, mass: 1.0 }.ease(0.999), 1.0);
+        
+        // without anything pulling it back it's already there
+        assert_near(Timing::Spring { stiffness: 0.0, damping: 1.0, mass: 1.0 }.ease(0.5), 1.0);
+    }
+    
+    #[test]
+    fn reversed_playback() {
+        let mut animation = animation(Direction::Normal, Fill::Forwards);
+        animation.sync(2000);
+        animation.rate = -1.0;
+        
+        // a second later it's back where it was a second in
+        assert_near(value(&animation, 3000), 0.0);
+        assert!(!animation.tick(2500).finished);
+        assert!(animation.tick(4000).finished);
     }
 }
@@ -1,22 +1,60 @@
 use crate::{
+    Json,
+    animations::AnimationX,
     engine::Engine,
+    events::{ChangeEvent, EventTy, MutationTy},
     macros::instance,
-    selectors::Selector,
     style::Style,
     wit::{
         traits::GuestElement,
-        types::{Element, InstanceId}
+        types::{Animation, Element, InstanceId}
     }
 };
 
 pub(crate) type ElementId = u64;
 
 #[derive(Copy, Clone, Default, Hash, Eq, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct ElementX {
     pub(crate) id: ElementId,
     pub(crate) instance_id: InstanceId
 }
 
+impl ElementX {
+    // the kind of mutation is passed in rather than guessed from the name, an attribute can be called `text` too
+    fn changed(&self, mutation: MutationTy) {
+        let (attribute, from, to) = match &mutation {
+            MutationTy::Attribute { name, from, to } => (name.clone(), from.clone(), to.clone()),
+            MutationTy::Text { from, to } => ("text".into(), from.clone(), to.clone()),
+            MutationTy::Style { from, to } => ("style".into(), from.clone(), to.clone()),
+            MutationTy::ChildAdded(_) | MutationTy::ChildRemoved(_) => return
+        };
+        
+        if from != to {
+            Engine::record(self.instance_id, self.id, mutation);
+            
+            Engine::emit(self.instance_id, EventTy::Change(ChangeEvent { attribute, from, to }), self.id, Json::Null);
+        }
+    }
+    
+    fn update_classes(&self, update: impl FnOnce(&mut std::collections::HashSet<String>)) {
+        let classes = instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| {
+            let prev = node.classes.clone();
+            update(&mut node.classes);
+            (prev, node.classes.clone())
+        });
+        
+        if let Some((prev, classes)) = classes {
+            let sorted = |classes: std::collections::HashSet<String>| {
+                let mut classes = classes.into_iter().collect::<Vec<_>>();
+                classes.sort();
+                Json::from(classes)
+            };
+            self.changed(MutationTy::Attribute { name: "classes".into(), from: sorted(prev), to: sorted(classes) });
+        }
+    }
+}
+
 impl GuestElement for ElementX {
     fn parent(&self) -> Option<Element> {
         instance!(self.instance_id)
@@ -53,6 +91,8 @@ impl GuestElement for ElementX {
                 
                 let prev_parent_layout_id = instance!(self.instance_id).nodes[&prev_p].layout_id;
                 instance!(self.instance_id).layout.remove_child(prev_parent_layout_id, layout_id);
+                
+                Engine::record(self.instance_id, prev_p, MutationTy::ChildRemoved(*self));
             }
         }
         
@@ -81,6 +121,8 @@ impl GuestElement for ElementX {
                     
                     instance!(self.instance_id).layout.add_child(parent_layout_id, layout_id);
                 }
+                
+                Engine::record(self.instance_id, parent.id, MutationTy::ChildAdded(*self));
             }
         }
         
@@ -138,17 +180,29 @@ impl GuestElement for ElementX {
     }
     
     fn splice_children(&self, range: std::ops::Range<usize>, replacement: Vec<Element>) -> Vec<Element> {
-        instance!(self.instance_id)
-            .nodes
-            .get_mut(&self.id)
-            .map(|node| {
-                node
-                    .children
-                    .splice(range, replacement.into_iter().map(|el| el.id))
-                    .map(|c| ElementX { id: c, instance_id: self.instance_id })
-                    .collect()
-            })
-            .unwrap_or_default()
+        let removed: Vec<Element> =
+            instance!(self.instance_id)
+                .nodes
+                .get_mut(&self.id)
+                .map(|node| {
+                    node
+                        .children
+                        .splice(range, replacement.iter().map(|el| el.id))
+                        .map(|c| ElementX { id: c, instance_id: self.instance_id })
+                        .collect()
+                })
+                .unwrap_or_default();
+        
+        if instance!(self.instance_id).nodes.contains_key(&self.id) {
+            for child in &removed {
+                Engine::record(self.instance_id, self.id, MutationTy::ChildRemoved(*child));
+            }
+            for child in &replacement {
+                Engine::record(self.instance_id, self.id, MutationTy::ChildAdded(*child));
+            }
+        }
+        
+        removed
     }
     
     fn siblings(&self) -> Vec<Element> {
@@ -215,18 +269,33 @@ impl GuestElement for ElementX {
     }
     
     fn add_event_listener(&self, event: &str, handler: &str) {
-        let selector = format!("%{}", self.id);
-        instance!(self.instance_id).listeners.entry(event.into()).or_default().push((Selector::parse(std::borrow::Cow::Owned(selector)), handler.into()));
+        Engine::listen(self.instance_id, event, Some(format!("%{}", self.id)), handler);
     }
     
-    // fn remove_event_listener(&self, event: &str, handler: &str) {
-    //     instance!(self.instance_id).listeners.entry(event.into()).or_default().retain(|(sel, h)| {
-    //         !(
-    //             sel.as_ref().map(|x| *x == format!("%{}", self.id)).unwrap_or_default()
-    //             && (h == handler)
-    //         )
-    //     });
-    // }
+    fn remove_event_listener(&self, event: &str, handler: &str) {
+        Engine::unlisten(self.instance_id, Some(&format!("%{}", self.id)), Some(event), Some(handler));
+    }
+    
+    fn remove_event_listeners(&self) {
+        Engine::unlisten(self.instance_id, Some(&format!("%{}", self.id)), None, None);
+    }
+    
+    fn focus(&self) {
+        if instance!(self.instance_id).nodes.contains_key(&self.id) {
+            Engine::focus(self.instance_id, self.id);
+        }
+    }
+    
+    fn blur(&self) {
+        // focus falls back to the root, like it does to the body in a browser
+        let (focused, root) = {
+            let engine = instance!(self.instance_id);
+            (engine.state.focused, engine.root)
+        };
+        if focused == self.id {
+            Engine::focus(self.instance_id, root.unwrap_or_default());
+        }
+    }
     
     fn attribute(&self, name: &str) -> Option<Json> {
         instance!(self.instance_id).nodes.get(&self.id).map(|node| node.attributes.get(name).cloned()).flatten()
@@ -237,11 +306,20 @@ impl GuestElement for ElementX {
     }
     
     fn set_attribute<T: Into<Json>>(&self, name: &str, value: T) -> Option<Json> {
-        instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.attributes.insert(name.into(), value.into())).unwrap_or_default()
+        let value = value.into();
+        let prev = instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.attributes.insert(name.into(), value.clone()));
+        if let Some(prev) = &prev {
+            self.changed(MutationTy::Attribute { name: name.into(), from: prev.clone().unwrap_or_default(), to: value });
+        }
+        prev.flatten()
     }
     
     fn remove_attribute(&self, name: &str) -> Option<Json> {
-        instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.attributes.remove(name)).unwrap_or_default()
+        let prev = instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.attributes.remove(name)).unwrap_or_default();
+        if let Some(prev) = &prev {
+            self.changed(MutationTy::Attribute { name: name.into(), from: prev.clone(), to: Json::Null });
+        }
+        prev
     }
     
     fn text(&self) -> Option<String> {
@@ -249,7 +327,12 @@ impl GuestElement for ElementX {
     }
     
     fn set_text(&self, new_text: impl Into<String>) -> Option<String> {
-        instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.text.replace(new_text.into())).unwrap_or_default()
+        let new_text = new_text.into();
+        let prev = instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.text.replace(new_text.clone()));
+        if let Some(prev) = &prev {
+            self.changed(MutationTy::Text { from: prev.clone().into(), to: new_text.into() });
+        }
+        prev.flatten()
     }
     
     fn tag(&self) -> String {
@@ -261,13 +344,36 @@ impl GuestElement for ElementX {
     }
     
     fn set_id(&self, new_id: impl Into<String>) -> Option<String> {
-        instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.id.replace(new_id.into())).flatten()
+        let new_id = new_id.into();
+        let prev = instance!(self.instance_id).nodes.get_mut(&self.id).map(|node| node.id.replace(new_id.clone()));
+        if let Some(prev) = &prev {
+            self.changed(MutationTy::Attribute { name: "id".into(), from: prev.clone().into(), to: new_id.into() });
+        }
+        prev.flatten()
     }
     
     fn classes(&self) -> Vec<String> {
         instance!(self.instance_id).nodes.get(&self.id).map(|node| node.classes.iter().cloned().collect()).unwrap_or_default()
     }
     
+    fn add_classes(&self, new_classes: Vec<String>) {
+        self.update_classes(|classes| classes.extend(new_classes));
+    }
+    
+    fn remove_classes(&self, old_classes: Vec<String>) {
+        self.update_classes(|classes| classes.retain(|class| !old_classes.contains(class)));
+    }
+    
+    fn toggle_classes(&self, toggled_classes: Vec<String>) {
+        self.update_classes(|classes| {
+            for class in toggled_classes {
+                if !classes.remove(&class) {
+                    classes.insert(class);
+                }
+            }
+        });
+    }
+    
     fn closest_ancestor(&self, selector: &str) -> Option<Element> {
         let matches = Engine::select(self.instance_id, selector);
         self.ancestors().into_iter().find(|el| matches.contains(el))
@@ -285,8 +391,27 @@ impl GuestElement for ElementX {
         instance!(self.instance_id).nodes.get(&self.id).map(|node| node.style.clone()).unwrap_or_default()
     }
     
-    fn set_style(&self, new: Style) {
+    fn set_style(&self, new: Style) -> Style {
+        let Some(prev) = Engine::set_style(self.instance_id, self.id, new.clone()) else {
+            return Style::default();
+        };
+        
+        self.changed(MutationTy::Style {
+            from: serde_json::to_value(&prev).unwrap_or_default(),
+            to: serde_json::to_value(&new).unwrap_or_default()
+        });
+        prev
+    }
+    
+    fn animate(&self, animation: String) -> Option<Animation> {
+        // described in JSON, `None` if it couldn't be parsed
+        let animation = serde_json::from_str::<crate::animations::Animation>(&animation).ok()?;
+        if !instance!(self.instance_id).nodes.contains_key(&self.id) {
+            return None;
+        }
         
+        let id = Engine::animate(self.instance_id, self.id, animation);
+        Some(Animation::new(AnimationX { id, instance_id: self.instance_id }))
     }
     
     fn scroll_to(&self) {
@@ -315,6 +440,8 @@ impl GuestElement for ElementX {
                 .nodes
                 .get_mut(&parent)
                 .map(|p| p.children.retain(|c| *c != self.id));
+            
+            Engine::record(self.instance_id, parent, MutationTy::ChildRemoved(self));
         }
         
         for child in &node.children {
@@ -323,7 +450,10 @@ impl GuestElement for ElementX {
         
         instance!(self.instance_id).layout.remove(node.layout_id);
         
-        instance!(self.instance_id).animations.retain(|(x, _)| *x != self.id);
+        Engine::remove_animations(&mut instance!(self.instance_id), |xid, _| xid == self.id);
+        instance!(self.instance_id).matched_rules.remove(&self.id);
+        
+        self.remove_event_listeners();
         
         children
     }
@@ -1,13 +1,13 @@
 use crate::{
     Json, InstanceId, Xid,
-    animations::Animation,
+    animations::{Animation, Stage},
     element::Element,
     events::*,
     macros::instance,
     node::{Node, Vertex},
-    selectors::{Selector, Op, Link},
-    style::Style,
-    text::TextResources
+    selectors::{self, Selector, SelectorError, Link, Nth, PseudoClass},
+    style::{Overflow, Style},
+    text::{TextResources, LINE_HEIGHT}
 };
 
 
@@ -22,13 +22,42 @@ pub(crate) enum Modifiers {
 }
 pub(crate) type ModifiersState = u8;
 
-#[derive(Copy, Clone, Default)]
+// where every timestamp comes from, in milliseconds
+#[derive(Clone, Copy)]
+pub(crate) enum Clock {
+    System,
+    // only moves when it's told to, for tests and hosts without a clock the engine can read, e.g. a browser,
+    // which set the time every frame from `performance.now()`
+    Manual(u128)
+}
+impl Default for Clock {
+    // there's no system clock to read on `wasm32-unknown-unknown`, `SystemTime::now` panics there
+    fn default() -> Self {
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            Clock::Manual(0)
+        } else {
+            Clock::System
+        }
+    }
+}
+impl Clock {
+    pub(crate) fn now(&self) -> u128 {
+        match self {
+            Clock::System => std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or_default(),
+            Clock::Manual(time) => *time
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub(crate) struct State {
     iid: InstanceId,
     pub(crate) mouse_position: lyon::math::Point,
     pub(crate) hovered: Xid,
     pub(crate) focused: Xid,
     pub(crate) window_size: lyon::math::Size,
+    pub(crate) fullscreen: bool,
     pub(crate) modifiers: ModifiersState
 }
 impl State {
@@ -41,6 +70,12 @@ impl State {
     pub fn focused(&self) -> Element {
         Element {xid: self.focused, iid: self.iid }
     }
+    pub fn window_size(&self) -> lyon::math::Size {
+        self.window_size
+    }
+    pub fn fullscreen(&self) -> bool {
+        self.fullscreen
+    }
     pub fn control_key_pressed(&self) -> bool {
         (self.modifiers & (Modifiers::Control as u8)) != 0
     }
@@ -62,18 +97,37 @@ pub(crate) struct Engine<'a> {
     pub(crate) layout: taffy::Taffy,
     pub(crate) nodes: std::collections::HashMap<Xid, Node>,
     pub(crate) root: Option<Xid>,
+    pub(crate) layout_dirty: bool,
     
     pub(crate) components: std::collections::HashMap<String, fn() -> Element>,
-    pub(crate) listeners: std::collections::HashMap<String, Vec<(Option<Selector<'a>>, String)>>,
+    // in the order they were added, which is the order they're called in
+    pub(crate) listeners: Vec<Listener>,
     pub(crate) handlers: std::collections::HashMap<String, std::sync::Arc<std::sync::Mutex<dyn Fn(Event) + Send + Sync + 'static>>>,
-    pub(crate) indicators: std::collections::HashMap<String, std::collections::HashSet<String>>,
-    pub(crate) requirements: std::collections::HashMap<String, std::collections::HashSet<String>>,
+    // how many listeners, observers and animations use each of them
+    pub(crate) anonymous_handlers: std::collections::HashMap<String, usize>,
     pub(crate) event_queue: Vec<Event>,
     pub(crate) event_queue_being_cleared: bool,
+    pub(crate) pressed: std::collections::HashMap<usize, Press>,
+    pub(crate) last_click: Option<Press>,
+    pub(crate) drag: Option<Drag>,
+    pub(crate) pointer_thresholds: PointerThresholds,
+    pub(crate) event_count: Xid,
     pub(crate) halted_events: std::collections::HashSet<Xid>,
+    pub(crate) immediately_halted_events: std::collections::HashSet<Xid>,
+    pub(crate) prevented_events: std::collections::HashSet<Xid>,
+    pub(crate) recording: Option<Vec<Record>>,
+    pub(crate) clock: Clock,
+    
+    pub(crate) observers: std::collections::HashMap<Xid, Observer>,
+    pub(crate) mutations: Vec<Mutation>,
+    pub(crate) flushing_mutations: bool,
     
     pub(crate) animations: Vec<(Xid, Animation)>,
-    pub(crate) stylesheet: Vec<(Selector<'a>, Style)>
+    pub(crate) animation_count: Xid,
+    pub(crate) stylesheet: Vec<(Selector<'a>, Style)>,
+    // the rule each node was last given, and the style it had before any rule was
+    pub(crate) matched_rules: std::collections::HashMap<Xid, (usize, Style)>,
+    pub(crate) stylesheet_dirty: bool
 }
 
 impl Engine<'_> {
@@ -84,19 +138,496 @@ impl Engine<'_> {
         iid
     }
     
+    // 0 is left to mean nothing, which is what's hovered, focused or targeted when there's no node to speak of
     pub(crate) fn xid(iid: InstanceId) -> Xid {
-        (0..Xid::MAX).find(|xid| !instance!(iid).nodes.contains_key(xid)).unwrap_or_default()
+        (1..Xid::MAX).find(|xid| !instance!(iid).nodes.contains_key(xid)).unwrap_or_default()
+    }
+    
+    pub(crate) fn resize(iid: InstanceId, width: f32, height: f32) {
+        let size = lyon::math::size(width, height);
+        if instance!(iid).state.window_size == size {
+            return;
+        }
+        
+        instance!(iid).state.window_size = size;
+        instance!(iid).layout_dirty = true;
+        
+        Engine::emit(iid, EventTy::Window(WindowEvent::Resize), 0, serde_json::json!({ "width": width, "height": height }));
+    }
+    
+    pub(crate) fn set_fullscreen(iid: InstanceId, fullscreen: bool) {
+        if instance!(iid).state.fullscreen == fullscreen {
+            return;
+        }
+        
+        instance!(iid).state.fullscreen = fullscreen;
+        
+        Engine::emit(iid, EventTy::Window(WindowEvent::Fullscreen), 0, serde_json::json!({ "fullscreen": fullscreen }));
+    }
+    
+    // which modifier keys are held, as the host sees them, for the `ctrl`, `alt`, `shift` and `super` requirements
+    pub(crate) fn set_modifiers(iid: InstanceId, modifiers: ModifiersState) {
+        instance!(iid).state.modifiers = modifiers;
+    }
+    
+    // keys go to whatever has focus, see the `EventTy::Key` branch of `Engine::emit`, returns whether the event was cancelled
+    pub(crate) fn key_down(iid: InstanceId, key: &str, modifiers: ModifiersState) -> bool {
+        Engine::set_modifiers(iid, modifiers);
+        Engine::emit(iid, EventTy::Key(KeyEvent::Down(key.into())), 0, Json::Null)
+    }
+    
+    pub(crate) fn key_up(iid: InstanceId, key: &str, modifiers: ModifiersState) -> bool {
+        Engine::set_modifiers(iid, modifiers);
+        Engine::emit(iid, EventTy::Key(KeyEvent::Up(key.into())), 0, Json::Null)
+    }
+    
+    // how far a node's content overflows it, according to the last computed layout
+    pub(crate) fn max_scroll_offset(iid: InstanceId, xid: Xid) -> lyon::math::Point {
+        let engine = instance!(iid);
+        
+        let Some(layout) = engine.nodes.get(&xid).map(|node| engine.layout.layout(node.layout_id).ok()).flatten() else {
+            return lyon::math::Point::zero();
+        };
+        
+        // child locations are relative to their parent
+        let content =
+            engine.nodes[&xid]
+                .children
+                .iter()
+                .filter_map(|c| engine.nodes.get(c))
+                .filter_map(|c| engine.layout.layout(c.layout_id).ok())
+                .fold(lyon::math::Size::zero(), |acc, l| {
+                    lyon::math::size(
+                        acc.width.max(l.location.x + l.size.width),
+                        acc.height.max(l.location.y + l.size.height)
+                    )
+                });
+        
+        lyon::math::point(
+            (content.width - layout.size.width).max(0.0),
+            (content.height - layout.size.height).max(0.0)
+        )
+    }
+    
+    // returns the offset actually set, after clamping it to the node's content
+    pub(crate) fn set_scroll_offset(iid: InstanceId, xid: Xid, scroll_offset: lyon::math::Point) -> lyon::math::Point {
+        let max = Engine::max_scroll_offset(iid, xid);
+        let clamped = lyon::math::point(scroll_offset.x.clamp(0.0, max.x), scroll_offset.y.clamp(0.0, max.y));
+        
+        instance!(iid).nodes.get_mut(&xid).map(|node| node.scroll_offset = clamped);
+        clamped
+    }
+    
+    // hands the delta to the closest scrollable ancestor of whatever's hovered, passing anything it can't take
+    // (because it's at its limit) on up to the next one, like a browser's scroll chaining
+    pub(crate) fn scroll(iid: InstanceId, delta: lyon::math::Vector, unit: ScrollUnit) {
+        let mut remaining = match unit {
+            ScrollUnit::Pixels => delta,
+            ScrollUnit::Lines => delta * LINE_HEIGHT
+        };
+        
+        let hovered = instance!(iid).state.hovered;
+        
+        for xid in std::iter::once(hovered).chain(Engine::ancestors(iid, hovered)) {
+            if remaining == lyon::math::Vector::zero() {
+                break;
+            }
+            
+            let Some((overflow, prev_offset)) = instance!(iid).nodes.get(&xid).map(|node| (node.style.overflow, node.scroll_offset)) else {
+                continue;
+            };
+            
+            let wanted = lyon::math::vector(
+                if overflow.0 == Overflow::Scroll { remaining.x } else { 0.0 },
+                if overflow.1 == Overflow::Scroll { remaining.y } else { 0.0 }
+            );
+            if wanted == lyon::math::Vector::zero() {
+                continue;
+            }
+            
+            let offset = Engine::set_scroll_offset(iid, xid, prev_offset + wanted);
+            let consumed = offset - prev_offset;
+            if consumed == lyon::math::Vector::zero() {
+                continue;
+            }
+            remaining -= consumed;
+            
+            let direction = if consumed.y.abs() >= consumed.x.abs() {
+                if consumed.y > 0.0 { ScrollEvent::Down } else { ScrollEvent::Up }
+            } else {
+                if consumed.x > 0.0 { ScrollEvent::Right } else { ScrollEvent::Left }
+            };
+            
+            Engine::emit(
+                iid,
+                EventTy::Scroll(direction),
+                xid,
+                serde_json::json!({
+                    "dx": consumed.x,
+                    "dy": consumed.y,
+                    "x": offset.x,
+                    "y": offset.y
+                })
+            );
+        }
+    }
+    
+    pub(crate) fn now(iid: InstanceId) -> u128 {
+        instance!(iid).clock.now()
+    }
+    
+    pub(crate) fn set_clock(iid: InstanceId, clock: Clock) {
+        instance!(iid).clock = clock;
+    }
+    
+    // only moves a manual clock, the others keep their own time
+    pub(crate) fn advance_clock(iid: InstanceId, delta: u128) {
+        let mut engine = instance!(iid);
+        if let Clock::Manual(time) = engine.clock {
+            engine.clock = Clock::Manual(time + delta);
+        }
     }
     
-    pub(crate) fn set_scroll_offset(iid: InstanceId, xid: Xid, scroll_offset: lyon::math::Point) {
-        instance!(iid).nodes.get_mut(&xid).map(|node| {
-            node.scroll_offset.x -= 10.0 * scroll_offset.x;
-            node.scroll_offset.y -= 10.0 * scroll_offset.y;
+    pub(crate) fn start_recording(iid: InstanceId) {
+        instance!(iid).recording = Some(vec![]);
+    }
+    
+    pub(crate) fn stop_recording(iid: InstanceId) -> Vec<Record> {
+        instance!(iid).recording.take().unwrap_or_default()
+    }
+    
+    // returns the first record that left the state different from how it was when recorded, if any did,
+    // though the rest are still replayed
+    pub(crate) fn replay(iid: InstanceId, records: Vec<Record>) -> Option<usize> {
+        // the clock is pinned to the recorded timestamps for the duration
+        let clock = instance!(iid).clock;
+        
+        let mut diverged = None;
+        for (idx, record) in records.into_iter().enumerate() {
+            instance!(iid).clock = Clock::Manual(record.timestamp);
+            Engine::input(iid, record.input);
+            
+            // which instance it was recorded in doesn't matter
+            let state = instance!(iid).state;
+            if (State { iid, ..record.state }) != state {
+                diverged = diverged.or(Some(idx));
+            }
+        }
+        
+        instance!(iid).clock = clock;
+        diverged
+    }
+    
+    // everything the host feeds in comes through here to be recorded, rather than the events it goes on to cause,
+    // so replaying it moves scroll offsets, drags, presses and so on along just the same
+    //
+    // returns whether the event was cancelled for the inputs that emit one directly, or for `Input::Advance` whether another frame is needed
+    pub(crate) fn input(iid: InstanceId, input: Input) -> bool {
+        let timestamp = Engine::now(iid);
+        let recorded = instance!(iid).recording.is_some().then(|| input.clone());
+        
+        let res = Engine::handle_input(iid, input);
+        
+        // along with the state it left behind, for a replay to be checked against
+        if let Some(input) = recorded {
+            let mut engine = instance!(iid);
+            let state = engine.state;
+            engine.recording.as_mut().map(|recording| recording.push(Record { timestamp, input, state }));
+        }
+        
+        res
+    }
+    
+    fn handle_input(iid: InstanceId, input: Input) -> bool {
+        match input {
+            Input::PointerMove(position) => Engine::pointer_move(iid, position),
+            Input::PointerDown(button, position) => Engine::pointer_down(iid, button, position),
+            Input::PointerUp(button, position) => Engine::pointer_up(iid, button, position),
+            Input::Scroll(delta, unit) => Engine::scroll(iid, delta, unit),
+            Input::DropPayload(position, payload) => Engine::drop_payload(iid, position, payload),
+            Input::KeyDown(key, modifiers) => return Engine::key_down(iid, &key, modifiers),
+            Input::KeyUp(key, modifiers) => return Engine::key_up(iid, &key, modifiers),
+            Input::SetModifiers(modifiers) => Engine::set_modifiers(iid, modifiers),
+            Input::Resize(width, height) => Engine::resize(iid, width, height),
+            Input::SetFullscreen(fullscreen) => Engine::set_fullscreen(iid, fullscreen),
+            Input::Trigger(event, target, extra) => return Engine::emit(iid, EventTy::Custom(event), target, extra),
+            Input::Advance => return Engine::advance(iid)
+        }
+        
+        false
+    }
+    
+    pub(crate) fn pointer_move(iid: InstanceId, position: lyon::math::Point) {
+        Engine::emit(iid, EventTy::Mouse(MouseEvent::Move(position)), 0, Json::Null);
+        Engine::drag_move(iid, position);
+    }
+    
+    pub(crate) fn pointer_down(iid: InstanceId, button: usize, position: lyon::math::Point) {
+        Engine::pointer_move(iid, position);
+        
+        let target = instance!(iid).state.hovered;
+        Engine::emit(iid, EventTy::Mouse(MouseEvent::Down(button)), target, Json::Null);
+        
+        instance!(iid).pressed.insert(button, Press { button, timestamp: Engine::now(iid), position, target, long: false });
+        instance!(iid).stylesheet_dirty = true;
+        
+        // only the primary button drags, and only once it's moved far enough, see `Engine::drag_move`
+        if button == 0 {
+            let source =
+                std::iter::once(target)
+                    .chain(Engine::ancestors(iid, target))
+                    .find(|xid| {
+                        instance!(iid)
+                            .nodes
+                            .get(xid)
+                            .map(|node| node.attributes.get("draggable") == Some(&Json::Bool(true)))
+                            .unwrap_or_default()
+                    });
+            
+            let drag = source.map(|source| Drag {
+                source,
+                payload: instance!(iid).nodes[&source].attributes.get("drag-data").cloned().unwrap_or_default(),
+                origin: position,
+                over: None,
+                active: false
+            });
+            instance!(iid).drag = drag;
+        }
+    }
+    
+    // clicks are only synthesised when the pointer is released over the same element it was pressed on
+    pub(crate) fn pointer_up(iid: InstanceId, button: usize, position: lyon::math::Point) {
+        Engine::pointer_move(iid, position);
+        
+        let target = instance!(iid).state.hovered;
+        Engine::emit(iid, EventTy::Mouse(MouseEvent::Up(button)), target, Json::Null);
+        
+        if button == 0 {
+            let drag = instance!(iid).drag.take();
+            if let Some(drag) = drag.filter(|drag| drag.active) {
+                let extra = drag.extra(position);
+                Engine::emit(iid, EventTy::Drag(DragEvent::Drop), target, extra.clone());
+                Engine::emit(iid, EventTy::Drag(DragEvent::End), drag.source, extra);
+                return;
+            }
+        }
+        
+        let Some(press) = instance!(iid).pressed.remove(&button) else {
+            return;
+        };
+        instance!(iid).stylesheet_dirty = true;
+        if press.target != target {
+            return;
+        }
+        
+        let thresholds = instance!(iid).pointer_thresholds;
+        let timestamp = Engine::now(iid);
+        let duration = timestamp.saturating_sub(press.timestamp);
+        let long = press.long || (duration >= thresholds.long_press_time);
+        
+        Engine::emit(
+            iid,
+            EventTy::Click(ClickEvent::from(button)),
+            target,
+            serde_json::json!({
+                "duration": duration as u64,
+                "long": long
+            })
+        );
+        
+        // a long press never makes half of a double click
+        if long {
+            return;
+        }
+        
+        let click = Press { button, timestamp, position, target, long };
+        let last_click = instance!(iid).last_click.take();
+        
+        let double_click = last_click.map(|last| {
+            (last.button == button)
+            && (last.target == target)
+            && (timestamp.saturating_sub(last.timestamp) <= thresholds.double_click_time)
+            && (position.distance_to(last.position) <= thresholds.double_click_distance)
+        }).unwrap_or_default();
+        
+        if double_click {
+            // a third click starts over rather than making another double click
+            Engine::emit(iid, EventTy::DoubleClick(DoubleClickEvent::from(button)), target, Json::Null);
+        } else {
+            instance!(iid).last_click = Some(click);
+        }
+    }
+    
+    fn drag_move(iid: InstanceId, position: lyon::math::Point) {
+        let Some(drag) = instance!(iid).drag.clone() else {
+            return;
+        };
+        
+        if !drag.active {
+            if position.distance_to(drag.origin) < instance!(iid).pointer_thresholds.drag_distance {
+                return;
+            }
+            
+            let cancelled = Engine::emit(iid, EventTy::Drag(DragEvent::Start), drag.source, drag.extra(position));
+            
+            let mut engine = instance!(iid);
+            if cancelled {
+                engine.drag = None;
+                return;
+            }
+            
+            // a drag is never also a click
+            engine.pressed.remove(&0);
+            engine.stylesheet_dirty = true;
+            engine.drag.as_mut().map(|drag| drag.active = true);
+        }
+        
+        // handlers may have changed the payload
+        let Some(drag) = instance!(iid).drag.clone() else {
+            return;
+        };
+        let over = instance!(iid).state.hovered;
+        
+        if drag.over != Some(over) {
+            if let Some(prev_over) = drag.over {
+                Engine::emit(iid, EventTy::Drag(DragEvent::Leave), prev_over, drag.extra(position));
+            }
+            Engine::emit(iid, EventTy::Drag(DragEvent::Enter), over, drag.extra(position));
+            
+            instance!(iid).drag.as_mut().map(|drag| drag.over = Some(over));
+        }
+        
+        Engine::emit(iid, EventTy::Drag(DragEvent::Over), over, drag.extra(position));
+    }
+    
+    // for things dragged in from outside the instance, e.g. files from the host
+    pub(crate) fn drop_payload(iid: InstanceId, position: lyon::math::Point, payload: Json) {
+        Engine::pointer_move(iid, position);
+        
+        let target = instance!(iid).state.hovered;
+        Engine::emit(
+            iid,
+            EventTy::Drag(DragEvent::Drop),
+            target,
+            serde_json::json!({
+                "payload": payload,
+                "source": null,
+                "x": position.x,
+                "y": position.y
+            })
+        );
+    }
+    
+    pub(crate) fn observe(iid: InstanceId, root: Xid, handler: String, subtree: bool) -> Xid {
+        let mut engine = instance!(iid);
+        
+        let id = (0..Xid::MAX).find(|id| !engine.observers.contains_key(id)).unwrap_or_default();
+        Engine::retain_handler(&mut engine, &handler);
+        engine.observers.insert(id, Observer { root, handler, subtree });
+        id
+    }
+    
+    pub(crate) fn unobserve(iid: InstanceId, id: Xid) -> bool {
+        let mut engine = instance!(iid);
+        
+        let removed = engine.observers.remove(&id);
+        if engine.observers.is_empty() {
+            engine.mutations.clear();
+        }
+        
+        let Some(observer) = removed else {
+            return false;
+        };
+        Engine::release_handler(&mut engine, &observer.handler);
+        true
+    }
+    
+    pub(crate) fn record(iid: InstanceId, target: Xid, ty: MutationTy) {
+        let mut engine = instance!(iid);
+        engine.stylesheet_dirty = true;
+        
+        // nobody would ever see it
+        if engine.observers.is_empty() {
+            return;
+        }
+        
+        engine.mutations.push(Mutation { target: Element { xid: target, iid }, ty });
+    }
+    
+    // delivers everything recorded since the last flush, batched per observer,
+    // which happens once an emit has finished dispatching and before each render
+    //
+    // observers are free to make changes of their own, which are left for the flush already underway to deliver in its next batch,
+    // rather than calling back into an observer that's still running
+    pub(crate) fn flush_mutations(iid: InstanceId) {
+        {
+            let mut engine = instance!(iid);
+            if engine.flushing_mutations {
+                return;
+            }
+            engine.flushing_mutations = true;
+        }
+        
+        loop {
+            let (mutations, observers) = {
+                let mut engine = instance!(iid);
+                if engine.mutations.is_empty() {
+                    break;
+                }
+                (std::mem::take(&mut engine.mutations), engine.observers.values().cloned().collect::<Vec<_>>())
+            };
+            
+            for observer in observers {
+                let records =
+                    mutations
+                        .iter()
+                        .filter(|m| {
+                            (m.target.xid == observer.root)
+                            || (observer.subtree && Engine::ancestors(iid, m.target.xid).contains(&observer.root))
+                        })
+                        .cloned()
+                        .collect::<Vec<_>>();
+                if records.is_empty() {
+                    continue;
+                }
+                
+                Engine::notify(iid, &observer.handler, EventTy::Mutation(records), observer.root, Json::Null);
+            }
+        }
+        
+        instance!(iid).flushing_mutations = false;
+    }
+    
+    // calls a single handler directly, for callbacks that aren't tied to a listener
+    pub(crate) fn notify(iid: InstanceId, handler: &str, ty: EventTy, target: Xid, extra: Json) {
+        let Some(fx) = instance!(iid).handlers.get(handler).cloned() else {
+            return;
+        };
+        
+        let state = instance!(iid).state;
+        (fx.lock().unwrap())(Event {
+            id: Engine::event_id(iid),
+            timestamp: Engine::now(iid),
+            ty,
+            state,
+            prev: state,
+            target: Element { xid: target, iid },
+            current: Element { xid: target, iid },
+            phase: Phase::Target,
+            src: Element { xid: target, iid },
+            extra
         });
-        // self.refresh_layout(&self.gfx);
     }
     
-    pub(crate) fn emit(iid: InstanceId, ty: EventTy, src: Xid, extra: Json) {
+    pub(crate) fn event_id(iid: InstanceId) -> Xid {
+        let mut engine = instance!(iid);
+        engine.event_count += 1;
+        engine.event_count
+    }
+    
+    // returns whether the event was cancelled, which is only known when it isn't emitted from within a handler,
+    // as those are queued until the current event's been dispatched
+    pub(crate) fn emit(iid: InstanceId, ty: EventTy, src: Xid, extra: Json) -> bool {
         // --- actions: specifiers ---
         // click: left, right, middle
         // mouse: move, enter, leave,
@@ -108,6 +639,7 @@ impl Engine<'_> {
         // window: resize, fullscreen
         // change: $
         // double click
+        // long press: left, right, middle
         // any, custom, regular
         // --- requirements ---
         // ctrl, alt, shift, super
@@ -118,13 +650,17 @@ impl Engine<'_> {
         // default, halt, prevent
         
         let state = instance!(iid).state;
+        let timestamp = Engine::now(iid);
         
         let mut event = Event {
-            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis(),
+            id: Engine::event_id(iid),
+            timestamp,
             ty,
             state: state.clone(),
             prev: state.clone(),
             target: Element { xid: src, iid },
+            current: Element { xid: src, iid },
+            phase: Phase::Target,
             src: Element { xid: src, iid },
             extra
         };
@@ -132,11 +668,11 @@ impl Engine<'_> {
         // println!("@@@: {}, {}, {}", &event.ty, &event.timestamp, &info);
         
         let mut current = vec![];
-        let mut nxt = vec![];
         
         match &event.ty {
-            EventTy::Animation(e) => {
+            EventTy::Animation(_) => {
                 // start, end, repeat
+                current.push(event.clone());
             },
             
             EventTy::Change(_) => {
@@ -145,57 +681,83 @@ impl Engine<'_> {
             
             EventTy::Click(_) => {
                 // TODO: context menu?
-                let nxt_focused = instance!(iid).state.hovered;
-                
-                event.state.focused = nxt_focused;
-                instance!(iid).event_queue.push(event.clone());
+                // focusing the clicked element is its default behaviour, see `Engine::default_action`
+                let hovered = instance!(iid).state.hovered;
                 
-                let new_focused = nxt_focused != event.prev.focused; //TODO: TAB FOCUS
-                if new_focused {
-                    instance!(iid).state.focused = nxt_focused;
-                    current.push(Event {
-                        ty: EventTy::Focus(FocusEvent::Out),
-                        target: Element { xid: event.prev.focused, iid },
-                        src: Element { xid: event.prev.focused, iid },
-                        ..event.clone()
-                    });
-                    current.push(Event {
-                        ty: EventTy::Focus(FocusEvent::In),
-                        ..event.clone()
-                    });
-                }
+                event.target = Element { xid: hovered, iid };
+                event.src = Element { xid: hovered, iid };
+                current.push(event.clone());
             },
             
-            EventTy::DoubleClick(e) => {
+            EventTy::DoubleClick(_) => {
+                let hovered = instance!(iid).state.hovered;
                 
+                event.target = Element { xid: hovered, iid };
+                event.src = Element { xid: hovered, iid };
+                current.push(event.clone());
             },
             
-            EventTy::Drag(e) => {
-                // start, end
+            EventTy::LongPress(_) => {
+                // fired while still held, so it goes to whatever was pressed rather than whatever's hovered now
+                current.push(event.clone());
             },
             
-            EventTy::Focus(e) => {
-                
+            EventTy::Drag(_) => {
+                // start, end, enter, over, leave, drop
+                current.push(event.clone());
+            },
+            
+            EventTy::Focus(_) => {
+                current.push(event.clone());
             },
             
             EventTy::Key(_) => {
                 // press, down, up
                 // TODO: special built-in behaviours like inspector, find, fullscreen, etc.?
+                // keys go to whatever has focus, which tab moves along as its default behaviour
+                let focused = instance!(iid).state.focused;
+                
+                event.target = Element { xid: focused, iid };
+                event.src = Element { xid: focused, iid };
                 current.push(event.clone());
             },
             
             EventTy::Mouse(e) => {
-                // instance!(id).state.mouse_position = e;
+                if let MouseEvent::Move(position) = e {
+                    instance!(iid).state.mouse_position = *position;
+                    event.state.mouse_position = *position;
+                }
+                
+                // gathered up front, the engine can't be borrowed again while the guard's held
+                let (position, candidates) = {
+                    let engine = instance!(iid);
+                    
+                    // a node is moved by everything it's scrolled inside of, the same as in `Engine::render`
+                    let scroll = |n: &Node| {
+                        let mut scroll = lyon::math::Vector::zero();
+                        let mut current = n.parent.map(|parent| engine.nodes.get(&parent)).flatten();
+                        while let Some(ancestor) = current {
+                            scroll += ancestor.scroll_offset.to_vector();
+                            current = ancestor.parent.map(|parent| engine.nodes.get(&parent)).flatten();
+                        }
+                        scroll
+                    };
+                    
+                    let candidates =
+                        engine
+                            .nodes
+                            .values()
+                            .filter_map(|n| engine.layout.layout(n.layout_id).ok().map(|layout| (n.xid, n.style.z, n.hidden, *layout, scroll(n))))
+                            .collect::<Vec<_>>();
+                    (engine.state.mouse_position, candidates)
+                };
+                
                 let nxt_hovered =
-                    instance!(iid)
-                        .nodes
-                        .values()
-                        .filter(|n| {
-                                n.is_within(instance!(iid).state.mouse_position)
-                                && !n.hidden
-                        })
-                        .reduce(|acc, item| { if acc.style.z > item.style.z { acc } else { item } })
-                        .map(|n| n.xid)
+                    candidates
+                        .into_iter()
+                        .filter(|(_, _, hidden, layout, scroll)| !hidden && Node::is_within(layout, position + *scroll))
+                        .reduce(|acc, item| { if acc.1 > item.1 { acc } else { item } })
+                        .map(|(xid, ..)| xid)
                         .unwrap_or_default();
                     
                 event.state.hovered = nxt_hovered;
@@ -207,204 +769,847 @@ impl Engine<'_> {
                 let new_hovered = nxt_hovered != event.prev.hovered;
                 if new_hovered {
                     instance!(iid).state.hovered = nxt_hovered;
+                    instance!(iid).stylesheet_dirty = true;
                     current.push(Event {
+                        id: Engine::event_id(iid),
                         ty: EventTy::Mouse(MouseEvent::Leave),
                         target: Element { xid: event.prev.hovered, iid },
                         src: Element { xid: event.prev.hovered, iid},
                         ..event.clone()
                     });
                     current.push(Event {
+                        id: Engine::event_id(iid),
                         ty: EventTy::Mouse(MouseEvent::Enter),
                         ..event.clone()
                     });
                 }
             },
             
-            EventTy::Scroll(e) => {
-                
+            EventTy::Scroll(_) => {
+                // the scrolling itself has already happened, see `Engine::scroll`
+                current.push(event.clone());
             },
             
-            EventTy::Window(e) => {
+            EventTy::Window(_) => {
                 // resize, fullscreen
+                // these belong to the whole instance, so they go to the root rather than whatever was passed in
+                let root = instance!(iid).root.unwrap_or_default();
+                
+                event.target = Element { xid: root, iid };
+                event.src = Element { xid: root, iid };
+                current.push(event.clone());
             },
             
-            EventTy::Custom(_) | EventTy::Any(_) => {
+            EventTy::Mutation(_) | EventTy::Custom(_) | EventTy::Any(_) => {
                 current.push(event.clone())
             }
         }
         
-        while !current.is_empty() {
-            for mut event in current {
-                instance!(iid).event_queue.push(event.clone());
-                // if let Some(parent) = this.borrow().parents.get(&event.target.0) {
-                //     if *parent != event.target.0 {
-                //         event.target.0 = *parent;
-                //         nxt.push(event);
-                //     }
-                // }
+        instance!(iid).event_queue.extend(current);
+        
+        if instance!(iid).event_queue_being_cleared {
+            return false;
+        }
+        
+        instance!(iid).event_queue_being_cleared = true;
+        
+        let mut queue = instance!(iid).event_queue.drain(..).collect::<Vec<_>>();
+        
+        while !queue.is_empty() {
+            for event in queue {
+                Engine::propagate(iid, event);
             }
-            current = nxt;
-            nxt = vec![];
+            
+            queue = instance!(iid).event_queue.drain(..).collect();
         }
         
-        if !instance!(iid).event_queue_being_cleared {
-            instance!(iid).event_queue_being_cleared = true;
+        let cancelled = instance!(iid).prevented_events.contains(&event.id);
+        
+        instance!(iid).halted_events.clear();
+        instance!(iid).immediately_halted_events.clear();
+        instance!(iid).prevented_events.clear();
+        
+        instance!(iid).event_queue_being_cleared = false;
+        
+        Engine::flush_mutations(iid);
+        
+        cancelled
+    }
+    
+    fn propagate(iid: InstanceId, event: Event) {
+        // capture from the root down to the target, then bubble back up
+        let ancestors = Engine::ancestors(iid, event.target.xid);
+        let bubbles = event.ty.bubbles();
+        
+        let hops =
+            ancestors
+                .iter()
+                .rev()
+                .map(|xid| (Phase::Capture, *xid))
+                .chain(std::iter::once((Phase::Target, event.target.xid)))
+                .chain(ancestors.iter().filter(|_| bubbles).map(|xid| (Phase::Bubble, *xid)))
+                .map(|(phase, xid)| Event {
+                    phase,
+                    current: Element { xid, iid },
+                    ..event.clone()
+                })
+                .collect::<Vec<_>>();
+        
+        for hop in hops {
+            let halted = {
+                let engine = instance!(iid);
+                engine.halted_events.contains(&event.id) || engine.immediately_halted_events.contains(&event.id)
+            };
+            if halted {
+                break;
+            }
             
-            let mut queue = instance!(iid).event_queue.drain(..).collect::<Vec<_>>();
+            Engine::dispatch(iid, &hop);
+        }
+        
+        if !instance!(iid).prevented_events.contains(&event.id) {
+            Engine::default_action(iid, &event);
+        }
+    }
+    
+    fn default_action(iid: InstanceId, event: &Event) {
+        match &event.ty {
+            EventTy::Click(_) => {
+                // TODO: context menu?
+                Engine::focus(iid, event.target.xid);
+            },
             
-            while !queue.is_empty() {
-                for event in queue {
-                    // if instance!(id).halted_events.contains(&event.id) {
-                    //     continue;TODO
-                    // }
-                    
-                    // let num_dots = event.ty.matches('.').count();
-                    // (1..=(1 + num_dots))
-                    //     .filter_map(|n| event.ty.rsplitn(n, '.').last())
-                    //     .flat_map(|listener| {
-                    //         DOM
-                    //             .get()
-                    //             .unwrap()
-                    //             .lock()
-                    //             .unwrap()
-                    //             .listeners
-                    //             .get(listener)
-                    //             .into_iter()
-                    //             .flatten()
-                    //             .filter_map(|(selector, handler)| {
-                    //                 instance!(id).handlers.get(handler).cloned()
-                    //             }) //TODO: filter out event.target not in dom.select(selector)
-                    //             .collect::<Vec<_>>()
-                    //     })
-                    //     .for_each(|fx| fx(event.clone()));
+            EventTy::Key(KeyEvent::Down(key)) if key == "Tab" => {
+                let order = Engine::tab_order(iid);
+                if order.is_empty() {
+                    return;
                 }
                 
-                queue = instance!(iid).event_queue.drain(..).collect();
+                let backwards = event.state.shift_key_pressed();
+                let nxt = match order.iter().position(|xid| *xid == event.state.focused) {
+                    Some(idx) if backwards => (idx + order.len() - 1) % order.len(),
+                    Some(idx) => (idx + 1) % order.len(),
+                    None if backwards => order.len() - 1,
+                    None => 0
+                };
+                
+                Engine::focus(iid, order[nxt]);
+            },
+            
+            _ => {}
+        }
+    }
+    
+    pub(crate) fn focus(iid: InstanceId, xid: Xid) {
+        let prev_focused = instance!(iid).state.focused;
+        if xid == prev_focused {
+            return;
+        }
+        
+        Engine::emit(iid, EventTy::Focus(FocusEvent::Out), prev_focused, Json::Null);
+        instance!(iid).state.focused = xid;
+        instance!(iid).stylesheet_dirty = true;
+        Engine::emit(iid, EventTy::Focus(FocusEvent::In), xid, Json::Null);
+    }
+    
+    // the nodes that can be reached with tab, in the order they're reached:
+    // positive `tabindex`es first in ascending order, then everything at 0 in document order
+    fn tab_order(iid: InstanceId) -> Vec<Xid> {
+        let engine = instance!(iid);
+        
+        let mut roots = engine.root.into_iter().collect::<Vec<_>>();
+        if roots.is_empty() {
+            roots = engine.nodes.values().filter(|node| node.parent.is_none()).map(|node| node.xid).collect();
+            roots.sort();
+        }
+        
+        let mut order = vec![];
+        let mut stack = roots.into_iter().rev().collect::<Vec<_>>();
+        while let Some(xid) = stack.pop() {
+            let Some(node) = engine.nodes.get(&xid) else {
+                continue;
+            };
+            
+            // hidden nodes take their subtree with them
+            if node.hidden {
+                continue;
             }
             
-            instance!(iid).halted_events.clear();
+            if let Some(tab_index) = node.attributes.get("tabindex").map(|t| t.as_i64()).flatten() {
+                if !tab_index.is_negative() {
+                    order.push((tab_index, xid));
+                }
+            }
             
-            instance!(iid).event_queue_being_cleared = false;
+            stack.extend(node.children.iter().rev());
         }
+        
+        order.sort_by_key(|(tab_index, _)| if *tab_index == 0 { i64::MAX } else { *tab_index });
+        order.into_iter().map(|(_, xid)| xid).collect()
     }
     
+    fn ancestors(iid: InstanceId, xid: Xid) -> Vec<Xid> {
+        let engine = instance!(iid);
+        
+        let mut res = vec![];
+        let mut current = engine.nodes.get(&xid).map(|node| node.parent).flatten();
+        while let Some(parent) = current {
+            res.push(parent);
+            current = engine.nodes.get(&parent).map(|node| node.parent).flatten();
+        }
+        
+        res
+    }
+    
+    // selectors are kept as-is and only evaluated at dispatch, so elements created later are covered too
+    pub(crate) fn listen(iid: InstanceId, event: &str, selector: Option<String>, handler: &str) -> bool {
+        // an unparseable selector would otherwise listen to everything
+        if selector.as_ref().map(|selector| Selector::parse(std::borrow::Cow::Borrowed(selector)).is_err()).unwrap_or_default() {
+            return false;
+        }
+        
+        let mut engine = instance!(iid);
+        Engine::retain_handler(&mut engine, handler);
+        engine.listeners.push(Listener::new(event, selector, handler));
+        
+        true
+    }
+    
+    pub(crate) fn unlisten(iid: InstanceId, selector: Option<&str>, event: Option<&str>, handler: Option<&str>) {
+        let mut engine = instance!(iid);
+        
+        let (removed, kept) =
+            std::mem::take(&mut engine.listeners)
+                .into_iter()
+                .partition::<Vec<_>, _>(|listener| {
+                    event.map(|event| event == listener.event).unwrap_or(true)
+                    && (listener.selector.as_deref() == selector)
+                    && handler.map(|handler| handler == listener.handler).unwrap_or(true)
+                });
+        engine.listeners = kept;
+        
+        for listener in removed {
+            Engine::release_handler(&mut engine, &listener.handler);
+        }
+    }
+    
+    pub(crate) fn remove_handler(iid: InstanceId, handler: &str) -> bool {
+        let mut engine = instance!(iid);
+        
+        let removed = engine.handlers.remove(handler).is_some();
+        engine.anonymous_handlers.remove(handler);
+        
+        engine.listeners.retain(|listener| listener.handler != handler);
+        
+        removed
+    }
+    
+    // handlers created from closures can't be referred to by name, so they're counted as they're attached to listeners,
+    // observers and animations, and freed when the last of those lets go of them
+    //
+    // one that's been created but not attached yet has nothing to let go of it, so it stays put until it is
+    pub(crate) fn retain_handler(engine: &mut Engine, handler: &str) {
+        engine.anonymous_handlers.get_mut(handler).map(|uses| *uses += 1);
+    }
+    
+    pub(crate) fn release_handler(engine: &mut Engine, handler: &str) {
+        let Some(uses) = engine.anonymous_handlers.get_mut(handler) else {
+            return;
+        };
+        *uses = uses.saturating_sub(1);
+        if *uses == 0 {
+            engine.anonymous_handlers.remove(handler);
+            engine.handlers.remove(handler);
+        }
+    }
+    
+    fn dispatch(iid: InstanceId, event: &Event) {
+        let name = event.ty.to_string();
+        let num_dots = name.matches('.').count();
+        
+        let actions =
+            (1..=(1 + num_dots))
+                .filter_map(|n| name.rsplitn(n, '.').last())
+                .chain(std::iter::once("any"))
+                .collect::<Vec<_>>();
+        
+        // everything is collected up front so the instance guard is released before any handler runs,
+        // as handlers are free to call back into the engine
+        let listeners =
+            instance!(iid)
+                .listeners
+                .iter()
+                .filter(|listener| actions.contains(&listener.action.as_str()))
+                .cloned()
+                .collect::<Vec<_>>();
+        
+        for listener in listeners {
+            if instance!(iid).immediately_halted_events.contains(&event.id) {
+                break;
+            }
+            
+            if !listener.accepts(event) {
+                continue;
+            }
+            
+            if let Some(selector) = &listener.selector {
+                let matching = Engine::select(iid, selector);
+                
+                let in_scope = if listener.requirements.contains("outside") {
+                    let target_and_ancestors =
+                        std::iter::once(event.target.xid)
+                            .chain(Engine::ancestors(iid, event.target.xid))
+                            .collect::<Vec<_>>();
+                    !matching.iter().any(|el| target_and_ancestors.contains(&el.xid))
+                } else {
+                    matching.contains(&event.current)
+                };
+                
+                if !in_scope {
+                    continue;
+                }
+            }
+            
+            let Some(fx) = instance!(iid).handlers.get(&listener.handler).cloned() else {
+                continue;
+            };
+            (fx.lock().unwrap())(event.clone());
+            
+            if listener.indicators.contains("halt") {
+                event.halt();
+            }
+            if listener.indicators.contains("prevent") {
+                event.prevent_default();
+            }
+        }
+    }
+    
+    // any part of the list that doesn't parse just matches nothing
     pub(crate) fn select(iid: InstanceId, selectors: &str) -> Vec<Element> {
+        let selectors =
+            selectors::split_list(selectors)
+                .into_iter()
+                .filter_map(|(_, s)| Selector::parse(std::borrow::Cow::Borrowed(s)).ok())
+                .collect();
+        
+        Engine::select_all(iid, selectors)
+    }
+    
+    // like `select`, but the first part of the list that doesn't parse fails the whole thing
+    pub(crate) fn try_select(iid: InstanceId, selectors: &str) -> Result<Vec<Element>, SelectorError> {
+        let selectors =
+            selectors::split_list(selectors)
+                .into_iter()
+                .map(|(offset, s)| Selector::parse(std::borrow::Cow::Borrowed(s)).map_err(|e| e.offset_by(offset)))
+                .collect::<Result<Vec<_>, _>>()?;
+        
+        Ok(Engine::select_all(iid, selectors))
+    }
+    
+    fn select_all(iid: InstanceId, selectors: Vec<Selector>) -> Vec<Element> {
         let mut res = std::collections::HashSet::new();
         
-        for selector in {
-            selectors
-                .split(',')
-                .filter_map(|s| Selector::parse(std::borrow::Cow::Borrowed(s)))
-        } {
-            let mut matching =
+        for selector in selectors {
+            let all =
                 instance!(iid)
                     .nodes
                     .keys()
                     .map(|xid| Element { xid: *xid, iid })
                     .collect::<Vec<_>>();
             
-            for (rule, link) in &selector.rules {
-                matching.retain(|el| {
-                    let node = &instance!(iid).nodes[&el.xid];
-                    
-                    if let Some(tag) = &rule.tag {
-                        if node.tag != selector.get(tag.0) {
-                            return false;
+            res.extend(Engine::select_from(iid, &selector, all));
+        }
+        
+        res.into_iter().collect()
+    }
+    
+    // narrows down `matching` rule by rule, following each rule's link to get the candidates for the next
+    pub(crate) fn select_from(iid: InstanceId, selector: &Selector, matching: Vec<Element>) -> Vec<Element> {
+        let mut matching = matching;
+        
+        // interaction state, like in a browser `:hover` and `:active` apply to the ancestors as well
+        let (hovered, focused, pressed) = {
+            let engine = instance!(iid);
+            (engine.state.hovered, engine.state.focused, engine.pressed.values().map(|press| press.target).collect::<Vec<_>>())
+        };
+        let with_ancestors = |xid: Xid| {
+            if xid == 0 {
+                return std::collections::HashSet::new();
+            }
+            std::iter::once(xid).chain(Engine::ancestors(iid, xid)).collect::<std::collections::HashSet<_>>()
+        };
+        let hovered = with_ancestors(hovered);
+        let focus_within = with_ancestors(focused);
+        let active = pressed.into_iter().flat_map(with_ancestors).collect::<std::collections::HashSet<_>>();
+        
+        for (rule, link) in &selector.rules {
+            // worked out up front, the engine can't be borrowed again while matching
+            let of =
+                rule
+                    .pseudo_classes
+                    .iter()
+                    .map(|pseudo_class| {
+                        let xids = |els: Vec<Element>| els.into_iter().map(|el| el.xid).collect::<std::collections::HashSet<_>>();
+                        match pseudo_class {
+                            PseudoClass::NthChild(Nth { of: Some(range), .. })
+                            | PseudoClass::NthLastChild(Nth { of: Some(range), .. })
+                            | PseudoClass::Not(range)
+                            | PseudoClass::Is(range)
+                            | PseudoClass::Where(range) => Some(xids(Engine::select(iid, selector.get(*range)))),
+                            // whichever of the candidates have something matching relative to them
+                            PseudoClass::Has(range) => {
+                                let relatives = selectors::split_list(selector.get(*range)).into_iter().filter_map(|(_, s)| selectors::relative(s).ok()).collect::<Vec<_>>();
+                                Some(
+                                    matching
+                                        .iter()
+                                        .filter(|el| {
+                                            relatives
+                                                .iter()
+                                                .any(|(link, relative)| !Engine::select_from(iid, relative, Engine::follow(link, **el)).is_empty())
+                                        })
+                                        .map(|el| el.xid)
+                                        .collect()
+                                )
+                            },
+                            _ => None
                         }
+                    })
+                    .collect::<Vec<_>>();
+            
+            matching.retain(|el| {
+                let engine = instance!(iid);
+                let node = &engine.nodes[&el.xid];
+                
+                let siblings = |filter: &dyn Fn(&Xid) -> bool| {
+                    node
+                        .parent
+                        .map(|parent| engine.nodes.get(&parent))
+                        .flatten()
+                        .map(|parent| parent.children.iter().copied().filter(|c| filter(c)).collect::<Vec<_>>())
+                        .unwrap_or(vec![node.xid])
+                };
+                // counting from 1, and from the end
+                let position = |filter: &dyn Fn(&Xid) -> bool| {
+                    let siblings = siblings(filter);
+                    siblings.iter().position(|c| *c == node.xid).map(|pos| ((pos + 1) as i64, (siblings.len() - pos) as i64))
+                };
+                let same_type = |c: &Xid| engine.nodes.get(c).map(|c| c.tag == node.tag).unwrap_or_default();
+                
+                if let Some(tag) = &rule.tag {
+                    if node.tag != selector.get(tag.0) {
+                        return false;
                     }
-                    
-                    if let Some(xid) = &rule.xid {
-                        if node.xid.to_string() != selector.get(xid.0) {
-                            return false;
-                        }
+                }
+                
+                if let Some(xid) = &rule.xid {
+                    if node.xid.to_string() != selector.get(xid.0) {
+                        return false;
                     }
-                    
-                    if let Some(id) = &rule.id {
-                        if let Some(nid) = &node.id {
-                            if nid != selector.get(id.0) {
-                                return false;
-                            }
-                        } else {
+                }
+                
+                if let Some(id) = &rule.id {
+                    if let Some(nid) = &node.id {
+                        if nid != selector.get(id.0) {
                             return false;
                         }
-                    }
-                    
-                    if !rule.classes.iter().all(|class| node.classes.contains(selector.get(class.0))) {
+                    } else {
                         return false;
                     }
+                }
+                
+                if !rule.classes.iter().all(|class| node.classes.contains(selector.get(class.0))) {
+                    return false;
+                }
+                
+                for attr in &rule.attributes {
+                    let Some(value) = node.attributes.get(selector.get(attr.name.0)) else {
+                        return false;
+                    };
                     
-                    for attr in &rule.attributes {
-                        match &attr.op {
-                            Op::Exists => {
-                                if !node.attributes.contains_key(selector.get(attr.name.0)) {
-                                    return false;
-                                }
-                            },
-                            op => {
-                                let Some(value) = attr.value else {
-                                    return false;
-                                };
-                                
-                                if let Some(nvalue) = node.attributes.get(selector.get(attr.name.0)) {
-                                    let v = serde_json::to_string(nvalue).unwrap().replace('"', "");
-                                    let valid = match op {
-                                        Op::Equals => v == selector.get(value),
-                                        Op::NotEquals => v != selector.get(value),
-                                        Op::StartsWith => v.starts_with(selector.get(value)),
-                                        Op::Contains => v.contains(selector.get(value)),
-                                        Op::EndsWith => v.ends_with(selector.get(value)),
-                                        _ => false
-                                    };
-                                    if !valid {
-                                        return false;
-                                    }
-                                } else {
-                                    return false;
-                                }
-                            }
-                        }
+                    // strings are compared as they are, anything else as it'd be written in JSON
+                    let valid = match value {
+                        Json::String(value) => attr.matches(value.as_str()),
+                        value => attr.matches(&value.to_string())
+                    };
+                    if !valid {
+                        return false;
                     }
-                    
-                    true
-                });
+                }
                 
-                if let Some(link) = link {
-                    match link {
-                        Link::Parent | Link::NextSibling | Link::PrevSibling => {
-                            matching =
-                                matching
-                                    .into_iter()
-                                    .filter_map(|el| match link {
-                                        Link::Parent => el.parent(),
-                                        Link::NextSibling => el.next_sibling(),
-                                        Link::PrevSibling => el.prev_sibling(),
-                                        _ => None
-                                    })
-                                    .collect();
+                for (pseudo_class, of) in rule.pseudo_classes.iter().zip(&of) {
+                    let of = |c: &Xid| of.as_ref().map(|of| of.contains(c)).unwrap_or(true);
+                    
+                    let valid = match pseudo_class {
+                        PseudoClass::Hover => hovered.contains(&node.xid),
+                        PseudoClass::Focus => (focused != 0) && (node.xid == focused),
+                        PseudoClass::FocusWithin => focus_within.contains(&node.xid),
+                        PseudoClass::Active => active.contains(&node.xid),
+                        PseudoClass::Disabled => {
+                            node
+                                .attributes
+                                .get("disabled")
+                                .map(|disabled| !(disabled.is_null() || (disabled == &Json::Bool(false))))
+                                .unwrap_or_default()
                         },
-                        _ => {
-                            matching =
-                                matching
-                                    .into_iter()
-                                    .flat_map(|el| match link {
-                                        Link::Ancestors => el.ancestors(),
-                                        Link::Descendants => el.descendants(),
-                                        Link::Children => el.children(),
-                                        Link::NextSiblings => el.next_siblings(),
-                                        Link::PrevSiblings => el.prev_siblings(),
-                                        Link::Siblings => el.siblings(),
-                                        _ => vec![]
-                                    })
-                                    .collect();
-                        }
+                        PseudoClass::FirstChild => siblings(&|_| true).first() == Some(&node.xid),
+                        PseudoClass::LastChild => siblings(&|_| true).last() == Some(&node.xid),
+                        PseudoClass::OnlyChild => siblings(&|_| true).len() == 1,
+                        PseudoClass::Empty => node.children.is_empty() && node.text.as_deref().unwrap_or_default().is_empty(),
+                        PseudoClass::Root => engine.root.map(|root| root == node.xid).unwrap_or(node.parent.is_none()),
+                        PseudoClass::NthChild(nth) => position(&of).map(|(idx, _)| nth.matches(idx)).unwrap_or_default(),
+                        PseudoClass::NthLastChild(nth) => position(&of).map(|(_, idx)| nth.matches(idx)).unwrap_or_default(),
+                        PseudoClass::NthOfType(nth) => position(&same_type).map(|(idx, _)| nth.matches(idx)).unwrap_or_default(),
+                        PseudoClass::NthLastOfType(nth) => position(&same_type).map(|(_, idx)| nth.matches(idx)).unwrap_or_default(),
+                        PseudoClass::Not(_) => !of(&node.xid),
+                        PseudoClass::Is(_) | PseudoClass::Where(_) | PseudoClass::Has(_) => of(&node.xid)
+                    };
+                    if !valid {
+                        return false;
                     }
                 }
+                
+                true
+            });
+            
+            if let Some(link) = link {
+                matching = matching.into_iter().flat_map(|el| Engine::follow(link, el)).collect();
+            }
+        }
+        
+        matching
+    }
+    
+    pub(crate) fn follow(link: &Link, el: Element) -> Vec<Element> {
+        match link {
+            Link::Parent => el.parent().into_iter().collect(),
+            Link::NextSibling => el.next_sibling().into_iter().collect(),
+            Link::PrevSibling => el.prev_sibling().into_iter().collect(),
+            Link::Ancestors => el.ancestors(),
+            Link::Descendants => el.descendants(),
+            Link::Children => el.children(),
+            Link::NextSiblings => el.next_siblings(),
+            Link::PrevSiblings => el.prev_siblings(),
+            Link::Siblings => el.siblings()
+        }
+    }
+    
+    // steps every running animation to the current time, and fires `longpress` for anything that's been held long enough,
+    // returning whether either is left to need another frame
+    pub(crate) fn advance(iid: InstanceId) -> bool {
+        let now = Engine::now(iid);
+        
+        let long_presses = {
+            let mut engine = instance!(iid);
+            let long_press_time = engine.pointer_thresholds.long_press_time;
+            engine
+                .pressed
+                .values_mut()
+                .filter(|press| !press.long && (now.saturating_sub(press.timestamp) >= long_press_time))
+                .map(|press| {
+                    press.long = true;
+                    *press
+                })
+                .collect::<Vec<_>>()
+        };
+        for press in long_presses {
+            Engine::emit(
+                iid,
+                EventTy::LongPress(LongPressEvent::from(press.button)),
+                press.target,
+                serde_json::json!({ "duration": now.saturating_sub(press.timestamp) as u64 })
+            );
+        }
+        
+        let samples =
+            instance!(iid)
+                .animations
+                .iter()
+                .map(|(xid, animation)| (*xid, animation.tick(now)))
+                .collect::<Vec<_>>();
+        
+        for (xid, sample) in &samples {
+            for (property, value) in &sample.values {
+                Engine::set_property(iid, *xid, property, value.clone());
+            }
+        }
+        
+        let mut events = vec![];
+        {
+            let mut engine = instance!(iid);
+            
+            for ((xid, animation), (_, sample)) in engine.animations.iter_mut().zip(&samples) {
+                if (sample.stage != Stage::Before) && !animation.started {
+                    animation.started = true;
+                    events.push((*xid, AnimationEvent::Start(animation.clone()), animation.iteration));
+                }
+                if (sample.stage == Stage::Active) && (sample.iteration != animation.iteration) {
+                    animation.iteration = sample.iteration;
+                    events.push((*xid, AnimationEvent::Repeat(animation.clone()), animation.iteration));
+                }
+                if sample.finished {
+                    events.push((*xid, AnimationEvent::End(animation.clone()), sample.iteration));
+                }
             }
             
-            res.extend(matching);
+            let mut samples = samples.iter();
+            engine.animations.retain(|_| samples.next().map(|(_, sample)| !sample.finished).unwrap_or(true));
         }
         
-        res.into_iter().collect()
+        for (xid, event, iteration) in events {
+            let on_finish = match &event {
+                AnimationEvent::End(animation) => animation.on_finish.clone(),
+                _ => None
+            };
+            let extra = serde_json::json!({ "iteration": iteration });
+            
+            Engine::emit(iid, EventTy::Animation(event.clone()), xid, extra.clone());
+            if let Some(handler) = on_finish {
+                Engine::notify(iid, &handler, EventTy::Animation(event), xid, extra);
+                Engine::release_handler(&mut instance!(iid), &handler);
+            }
+        }
+        
+        // paused ones can wait for whatever resumes them
+        let engine = instance!(iid);
+        engine.animations.iter().any(|(_, animation)| !animation.paused) || engine.pressed.values().any(|press| !press.long)
+    }
+    
+    // returns `None` once the animation's finished or been cancelled
+    pub(crate) fn with_animation<T>(iid: InstanceId, id: Xid, f: impl FnOnce(&mut Animation, u128) -> T) -> Option<T> {
+        let now = Engine::now(iid);
+        
+        let mut engine = instance!(iid);
+        engine.animations.iter_mut().find(|(_, animation)| animation.id == id).map(|(_, animation)| f(animation, now))
+    }
+    
+    // replaces whatever the animation was going to call once it's finished
+    pub(crate) fn on_finish(iid: InstanceId, id: Xid, handler: String) {
+        let mut engine = instance!(iid);
+        let Some(idx) = engine.animations.iter().position(|(_, animation)| animation.id == id) else {
+            return;
+        };
+        
+        Engine::retain_handler(&mut engine, &handler);
+        let prev = std::mem::replace(&mut engine.animations[idx].1.on_finish, Some(handler));
+        if let Some(prev) = prev {
+            Engine::release_handler(&mut engine, &prev);
+        }
+    }
+    
+    // takes out the animations that match, letting go of the handlers they'd have called on finishing
+    pub(crate) fn remove_animations(engine: &mut Engine, f: impl Fn(Xid, &Animation) -> bool) -> Vec<(Xid, Animation)> {
+        let (removed, kept) =
+            std::mem::take(&mut engine.animations)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(xid, animation)| f(*xid, animation));
+        engine.animations = kept;
+        
+        for handler in removed.iter().filter_map(|(_, animation)| animation.on_finish.as_deref()) {
+            Engine::release_handler(engine, handler);
+        }
+        removed
+    }
+    
+    // stops an animation where it is and puts back whatever it changed
+    pub(crate) fn cancel_animation(iid: InstanceId, id: Xid) {
+        let Some(animation) = Engine::remove_animations(&mut instance!(iid), |_, animation| animation.id == id).pop() else {
+            return;
+        };
+        
+        let (xid, animation) = animation;
+        for (property, value) in animation.base {
+            Engine::set_property(iid, xid, &property, value);
+        }
+    }
+    
+    // starts a keyframe animation on a node from now, remembering where its properties were beforehand
+    pub(crate) fn animate(iid: InstanceId, xid: Xid, animation: Animation) -> Xid {
+        let id = {
+            let mut engine = instance!(iid);
+            engine.animation_count += 1;
+            engine.animation_count
+        };
+        
+        // only what it describes is kept, the playback state always starts afresh
+        let mut animation = Animation {
+            id,
+            delay: animation.delay,
+            iterations: animation.iterations,
+            direction: animation.direction,
+            fill: animation.fill,
+            synced: Engine::now(iid),
+            ..Animation::new(animation.keyframes, animation.duration)
+        };
+        animation.base =
+            animation
+                .keyframes
+                .keys()
+                .filter_map(|property| Engine::property(iid, xid, property).map(|value| (property.clone(), value)))
+                .collect();
+        
+        instance!(iid).animations.push((xid, animation));
+        id
+    }
+    
+    // reads the style property at a dotted path, e.g. `transform.rotate`, or failing that the attribute
+    pub(crate) fn property(iid: InstanceId, xid: Xid, property: &str) -> Option<Json> {
+        let engine = instance!(iid);
+        let node = engine.nodes.get(&xid)?;
+        
+        let pointer = format!("/{}", property.replace('.', "/"));
+        let style = serde_json::to_value(&node.style).unwrap_or_default();
+        
+        style.pointer(&pointer).cloned().or_else(|| node.attributes.get(property).cloned())
+    }
+    
+    // replaces a node's style, returning the old one, except that any numeric property with a transition declared
+    // for it is animated from its old value instead of snapping to the new one
+    pub(crate) fn set_style(iid: InstanceId, xid: Xid, new: Style) -> Option<Style> {
+        let prev = instance!(iid).nodes.get(&xid).map(|node| node.style.clone())?;
+        
+        let from = serde_json::to_value(&prev).unwrap_or_default();
+        let mut applied = serde_json::to_value(&new).unwrap_or_default();
+        
+        let now = Engine::now(iid);
+        let mut transitions = vec![];
+        for (property, (duration, timing)) in &new.transitions {
+            let pointer = format!("/{}", property.replace('.', "/"));
+            
+            let Some(start) = from.pointer(&pointer).map(|v| v.as_f64()).flatten() else {
+                continue;
+            };
+            let Some(slot) = applied.pointer_mut(&pointer) else {
+                continue;
+            };
+            let Some(end) = slot.as_f64() else {
+                continue;
+            };
+            if (start == end) || (*duration <= 0.0) {
+                continue;
+            }
+            
+            // held at the old value until the animation moves it along
+            *slot = start.into();
+            transitions.push(Animation::transition(property, start as f32, end as f32, *duration, timing.clone(), now));
+        }
+        
+        let applied = serde_json::from_value::<Style>(applied).unwrap_or(new);
+        
+        let mut engine = instance!(iid);
+        let node = engine.nodes.get_mut(&xid)?;
+        let layout_id = node.layout_id;
+        node.style = applied.clone();
+        let _ = engine.layout.set_style(layout_id, applied.layout);
+        
+        // a new transition on a property takes over from whatever was already animating it
+        for (_, animation) in engine.animations.iter_mut().filter(|(x, _)| *x == xid) {
+            animation.keyframes.retain(|property, _| !transitions.iter().any(|t| t.keyframes.contains_key(property)));
+        }
+        Engine::remove_animations(&mut engine, |_, animation| animation.keyframes.is_empty());
+        engine.animations.extend(transitions.into_iter().map(|animation| (xid, animation)));
+        
+        Some(prev)
+    }
+    
+    // gives every node the style of the most specific rule in the stylesheet that matches it (the last, of those that are as specific),
+    // and gives a node back the style it had before once no rule matches it anymore
+    //
+    // only worth doing once something a selector can see has changed: the tree, attributes, or interaction state
+    pub(crate) fn apply_stylesheet(iid: InstanceId) {
+        let selectors = {
+            let engine = instance!(iid);
+            if !engine.stylesheet_dirty {
+                return;
+            }
+            engine
+                .stylesheet
+                .iter()
+                .map(|(selector, _)| (selector.src.to_string(), selector.specificity()))
+                .collect::<Vec<_>>()
+        };
+        
+        let mut matches = std::collections::HashMap::new();
+        for (idx, (selector, specificity)) in selectors.iter().enumerate() {
+            for el in Engine::select(iid, selector) {
+                let beaten = matches.get(&el.xid).map(|(_, best)| best > specificity).unwrap_or_default();
+                if !beaten {
+                    matches.insert(el.xid, (idx, *specificity));
+                }
+            }
+        }
+        
+        // through `set_style`, so a declared transition animates the change
+        let restyle = |xid: Xid, style: Style| {
+            if let Some(prev) = Engine::set_style(iid, xid, style.clone()) {
+                Engine::record(
+                    iid,
+                    xid,
+                    MutationTy::Style {
+                        from: serde_json::to_value(&prev).unwrap_or_default(),
+                        to: serde_json::to_value(&style).unwrap_or_default()
+                    }
+                );
+            }
+        };
+        
+        let unmatched =
+            instance!(iid)
+                .matched_rules
+                .keys()
+                .filter(|xid| !matches.contains_key(xid))
+                .copied()
+                .collect::<Vec<_>>();
+        for xid in unmatched {
+            let Some((_, base)) = instance!(iid).matched_rules.remove(&xid) else {
+                continue;
+            };
+            restyle(xid, base);
+        }
+        
+        for (xid, (idx, _)) in matches {
+            let style = {
+                let mut engine = instance!(iid);
+                let Some(current) = engine.nodes.get(&xid).map(|node| node.style.clone()) else {
+                    continue;
+                };
+                match engine.matched_rules.get_mut(&xid) {
+                    Some((matched, _)) if *matched == idx => continue,
+                    Some((matched, _)) => *matched = idx,
+                    None => {
+                        engine.matched_rules.insert(xid, (idx, current));
+                    }
+                }
+                engine.stylesheet[idx].1.clone()
+            };
+            restyle(xid, style);
+        }
+        
+        // the styles just set were recorded as mutations, but they're nothing a selector can see
+        instance!(iid).stylesheet_dirty = false;
+    }
+    
+    // writes to the style property at a dotted path, e.g. `transform.rotate`, or failing that to the attribute
+    pub(crate) fn set_property(iid: InstanceId, xid: Xid, property: &str, value: Json) {
+        let mut engine = instance!(iid);
+        let Some(node) = engine.nodes.get_mut(&xid) else {
+            return;
+        };
+        
+        let pointer = format!("/{}", property.replace('.', "/"));
+        let mut style = serde_json::to_value(&node.style).unwrap_or_default();
+        
+        let Some(slot) = style.pointer_mut(&pointer) else {
+            node.attributes.insert(property.into(), value);
+            return;
+        };
+        *slot = value;
+        
+        let Ok(style) = serde_json::from_value::<Style>(style) else {
+            return;
+        };
+        let layout_id = node.layout_id;
+        node.style = style.clone();
+        let _ = engine.layout.set_style(layout_id, style.layout);
     }
     
     pub(crate) fn render(iid: InstanceId, root: Xid) -> lyon::tessellation::VertexBuffers<Vertex, u16> {
@@ -412,9 +1617,18 @@ impl Engine<'_> {
             return lyon::tessellation::VertexBuffers::new();
         }
         
-        instance!(iid).root = Some(root);
+        Engine::apply_stylesheet(iid);
+        Engine::flush_mutations(iid);
+        
+        let prev_root = instance!(iid).root.replace(root);
         let root_layout_id = instance!(iid).nodes[&root].layout_id;
         
+        // taffy only knows about changes to the nodes themselves, not the space they're laid out in
+        if instance!(iid).layout_dirty || (prev_root != Some(root)) {
+            taffy::tree::LayoutTree::mark_dirty(&mut instance!(iid).layout, root_layout_id);
+            instance!(iid).layout_dirty = false;
+        }
+        
         let size = instance!(iid).state.window_size;
         instance!(iid).layout.compute_layout(
             root_layout_id,
@@ -425,11 +1639,151 @@ impl Engine<'_> {
         );
         
         let mut buffers = lyon::tessellation::VertexBuffers::new();
-        for node in instance!(iid).nodes.values() {
-            let buffer = node.render();
+        let xids = instance!(iid).nodes.keys().copied().collect::<Vec<_>>();
+        for xid in xids {
+            // a node is moved by everything it's scrolled inside of
+            let scroll =
+                Engine::ancestors(iid, xid)
+                    .into_iter()
+                    .filter_map(|ancestor| instance!(iid).nodes.get(&ancestor).map(|node| node.scroll_offset.to_vector()))
+                    .fold(lyon::math::Vector::zero(), |acc, offset| acc + offset);
+            
+            let buffer = instance!(iid).nodes[&xid].render(scroll);
             buffers.indices.extend(buffer.indices);
             buffers.vertices.extend(buffer.vertices);
         }
         buffers
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // a line of nodes, each the only child of the one before, root first
+    fn chain(iid: InstanceId, len: Xid) -> Vec<Xid> {
+        Engine::new_instance(iid, 100.0, 100.0);
+        
+        let xids = (1..=len).collect::<Vec<_>>();
+        let mut engine = instance!(iid);
+        for (idx, xid) in xids.iter().enumerate() {
+            let layout_id = engine.layout.new_leaf(Default::default()).unwrap();
+            engine.nodes.insert(*xid, Node {
+                xid: *xid,
+                layout_id,
+                parent: idx.checked_sub(1).map(|parent| xids[parent]),
+                children: xids.get(idx + 1).copied().into_iter().collect(),
+                tag: "div".into(),
+                ..Default::default()
+            });
+        }
+        engine.root = xids.first().copied();
+        
+        xids
+    }
+    
+    // how many times a listener is called for a click on the deepest of 6 nodes
+    fn clicks(iid: InstanceId, event: &str, selector: Option<&str>) -> usize {
+        let xids = chain(iid, 6);
+        
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        instance!(iid).handlers.insert("count".into(), std::sync::Arc::new(std::sync::Mutex::new(move |_: Event| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        })));
+        assert!(Engine::listen(iid, event, selector.map(String::from), "count"));
+        
+        instance!(iid).state.hovered = xids[5];
+        Engine::emit(iid, EventTy::Click(ClickEvent::Left), xids[5], Json::Null);
+        
+        calls.load(std::sync::atomic::Ordering::SeqCst)
+    }
+    
+    #[test]
+    fn instance_listeners() {
+        // once for the whole instance, rather than once per ancestor
+        assert_eq!(clicks(1, "click", None), 1);
+        assert_eq!(clicks(2, "click.left", None), 1);
+        assert_eq!(clicks(3, "any", None), 1);
+        
+        // at the target and every ancestor it bubbles through
+        assert_eq!(clicks(4, "click", Some("div")), 6);
+        assert_eq!(clicks(5, "click", Some("div:not(:has(> div))")), 1);
+    }
+    
+    #[test]
+    fn interaction_state() {
+        let iid = 30;
+        let xids = chain(iid, 3);
+        let select = |selector: &str| Engine::select(iid, selector).len();
+        
+        // nothing's hovered or focused yet
+        assert_eq!(select(":hover"), 0);
+        assert_eq!(select(":focus"), 0);
+        assert_eq!(select(":focus-within"), 0);
+        assert_ne!(Engine::xid(iid), 0);
+        
+        instance!(iid).state.hovered = xids[1];
+        instance!(iid).state.focused = xids[2];
+        assert_eq!(select(":hover"), 2);
+        assert_eq!(select(":focus"), 1);
+        assert_eq!(select(":focus-within"), 3);
+    }
+    
+    #[test]
+    fn replay() {
+        let (recorded, replayed) = (20, 21);
+        chain(recorded, 3);
+        chain(replayed, 3);
+        
+        Engine::set_clock(recorded, Clock::Manual(1000));
+        Engine::start_recording(recorded);
+        Engine::input(recorded, Input::Resize(200.0, 100.0));
+        Engine::advance_clock(recorded, 16);
+        Engine::input(recorded, Input::PointerMove(lyon::math::point(10.0, 20.0)));
+        Engine::input(recorded, Input::SetModifiers(Modifiers::Shift as u8));
+        let records = Engine::stop_recording(recorded);
+        
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].timestamp, 1016);
+        assert_eq!(records[1].state.mouse_position, lyon::math::point(10.0, 20.0));
+        
+        // by way of the log, like the host would
+        let log = serde_json::to_string(&records).unwrap();
+        let mut records = serde_json::from_str::<Vec<Record>>(&log).unwrap();
+        assert_eq!(Engine::replay(replayed, records.clone()), None);
+        assert_eq!(instance!(replayed).state.window_size, lyon::math::size(200.0, 100.0));
+        assert!(instance!(replayed).state.shift_key_pressed());
+        
+        records[1].state.fullscreen = true;
+        assert_eq!(Engine::replay(replayed, records), Some(1));
+    }
+    
+    #[test]
+    fn anonymous_handlers() {
+        let iid = 10;
+        chain(iid, 1);
+        {
+            let mut engine = instance!(iid);
+            for handler in ["a", "b"] {
+                engine.handlers.insert(handler.into(), std::sync::Arc::new(std::sync::Mutex::new(|_: Event| {})));
+                engine.anonymous_handlers.insert(handler.into(), 0);
+            }
+        }
+        let exists = |handler: &str| instance!(iid).handlers.contains_key(handler);
+        
+        // nothing's used `b` yet, so letting go of everything else leaves it be
+        Engine::listen(iid, "click", Some("%1".into()), "a");
+        Engine::listen(iid, "focus", Some("%1".into()), "a");
+        let observer = Engine::observe(iid, 1, "a".into(), true);
+        Engine::unlisten(iid, Some("%1"), None, None);
+        assert!(exists("a") && exists("b"));
+        
+        Engine::unobserve(iid, observer);
+        assert!(!exists("a") && exists("b"));
+        
+        Engine::listen(iid, "click", None, "b");
+        Engine::unlisten(iid, None, Some("click"), Some("b"));
+        assert!(!exists("b"));
+    }
+}
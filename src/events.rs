@@ -1,41 +1,96 @@
 use crate::{
-    Json,
+    Json, Xid,
     animations::Animation,
     element::Element,
-    engine::{Engine, State},
+    engine::{Engine, ModifiersState, State},
     macros::instance
 };
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum MouseEvent {
     Move(lyon::math::Point),
+    Down(usize),
+    Up(usize),
     Enter,
     Leave
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum ScrollEvent {
     Up,
-    Down
+    Down,
+    Left,
+    Right
+}
+
+#[derive(Clone, Copy)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum ScrollUnit {
+    Pixels,
+    Lines
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum ClickEvent {
     Left,
     Right,
     Middle,
     Other(usize)
 }
+impl From<usize> for ClickEvent {
+    fn from(button: usize) -> Self {
+        match button {
+            0 => ClickEvent::Left,
+            1 => ClickEvent::Middle,
+            2 => ClickEvent::Right,
+            n => ClickEvent::Other(n)
+        }
+    }
+}
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum DoubleClickEvent {
     Left,
     Right,
     Middle,
     Other(usize)
 }
+impl From<usize> for DoubleClickEvent {
+    fn from(button: usize) -> Self {
+        match button {
+            0 => DoubleClickEvent::Left,
+            1 => DoubleClickEvent::Middle,
+            2 => DoubleClickEvent::Right,
+            n => DoubleClickEvent::Other(n)
+        }
+    }
+}
+
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum LongPressEvent {
+    Left,
+    Right,
+    Middle,
+    Other(usize)
+}
+impl From<usize> for LongPressEvent {
+    fn from(button: usize) -> Self {
+        match button {
+            0 => LongPressEvent::Left,
+            1 => LongPressEvent::Middle,
+            2 => LongPressEvent::Right,
+            n => LongPressEvent::Other(n)
+        }
+    }
+}
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum KeyEvent {
     Down(String),
     Up(String),
@@ -50,24 +105,32 @@ pub enum AnimationEvent {
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum DragEvent {
     Start,
+    Enter,
+    Over,
+    Leave,
+    Drop,
     End
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum WindowEvent {
     Resize,
     Fullscreen
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum FocusEvent {
     In,
     Out
 }
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct ChangeEvent {
     pub attribute: String,
     pub from: Json,
@@ -75,46 +138,332 @@ pub struct ChangeEvent {
 }
 
 #[derive(Clone)]
+pub enum MutationTy {
+    ChildAdded(Element),
+    ChildRemoved(Element),
+    Attribute {
+        name: String,
+        from: Json,
+        to: Json
+    },
+    Text {
+        from: Json,
+        to: Json
+    },
+    Style {
+        from: Json,
+        to: Json
+    }
+}
+
+#[derive(Clone)]
+pub struct Mutation {
+    pub target: Element,
+    pub ty: MutationTy
+}
+
+#[derive(Clone)]
+pub(crate) struct Observer {
+    pub(crate) root: Xid,
+    pub(crate) handler: String,
+    pub(crate) subtree: bool
+}
+
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub enum EventTy {
     Mouse(MouseEvent),
     Scroll(ScrollEvent),
     Click(ClickEvent),
     Key(KeyEvent),  // TODO: special built-in behaviours like inspector, find, fullscreen, etc.?
+    // generated by the engine itself, so there's never a need to record them
+    #[serde(skip)]
     Animation(AnimationEvent),
     Drag(DragEvent),
     Window(WindowEvent),
     Focus(FocusEvent),
     Change(ChangeEvent),
     DoubleClick(DoubleClickEvent),
+    LongPress(LongPressEvent),
+    #[serde(skip)]
+    Mutation(Vec<Mutation>),
     Custom(String),
     Any(Box<EventTy>)
 }
+impl std::fmt::Display for EventTy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventTy::Mouse(e) => match e {
+                MouseEvent::Move(_) => write!(f, "mouse.move"),
+                MouseEvent::Down(_) => write!(f, "mouse.down"),
+                MouseEvent::Up(_) => write!(f, "mouse.up"),
+                MouseEvent::Enter => write!(f, "mouse.enter"),
+                MouseEvent::Leave => write!(f, "mouse.leave")
+            },
+            EventTy::Scroll(e) => match e {
+                ScrollEvent::Up => write!(f, "scroll.up"),
+                ScrollEvent::Down => write!(f, "scroll.down"),
+                ScrollEvent::Left => write!(f, "scroll.left"),
+                ScrollEvent::Right => write!(f, "scroll.right")
+            },
+            EventTy::Click(e) => match e {
+                ClickEvent::Left => write!(f, "click.left"),
+                ClickEvent::Right => write!(f, "click.right"),
+                ClickEvent::Middle => write!(f, "click.middle"),
+                ClickEvent::Other(n) => write!(f, "click.{n}")
+            },
+            EventTy::Key(e) => match e {
+                KeyEvent::Down(_) => write!(f, "key.down"),
+                KeyEvent::Up(_) => write!(f, "key.up"),
+                KeyEvent::Press(_) => write!(f, "key.press")
+            },
+            EventTy::Animation(e) => match e {
+                AnimationEvent::Start(_) => write!(f, "animation.start"),
+                AnimationEvent::End(_) => write!(f, "animation.end"),
+                AnimationEvent::Repeat(_) => write!(f, "animation.repeat")
+            },
+            EventTy::Drag(e) => match e {
+                DragEvent::Start => write!(f, "drag.start"),
+                DragEvent::Enter => write!(f, "drag.enter"),
+                DragEvent::Over => write!(f, "drag.over"),
+                DragEvent::Leave => write!(f, "drag.leave"),
+                DragEvent::Drop => write!(f, "drag.drop"),
+                DragEvent::End => write!(f, "drag.end")
+            },
+            EventTy::Window(e) => match e {
+                WindowEvent::Resize => write!(f, "window.resize"),
+                WindowEvent::Fullscreen => write!(f, "window.fullscreen")
+            },
+            EventTy::Focus(e) => match e {
+                FocusEvent::In => write!(f, "focus.in"),
+                FocusEvent::Out => write!(f, "focus.out")
+            },
+            EventTy::Change(e) => write!(f, "change.{}", e.attribute),
+            EventTy::DoubleClick(e) => match e {
+                DoubleClickEvent::Left => write!(f, "doubleclick.left"),
+                DoubleClickEvent::Right => write!(f, "doubleclick.right"),
+                DoubleClickEvent::Middle => write!(f, "doubleclick.middle"),
+                DoubleClickEvent::Other(n) => write!(f, "doubleclick.{n}")
+            },
+            EventTy::LongPress(e) => match e {
+                LongPressEvent::Left => write!(f, "longpress.left"),
+                LongPressEvent::Right => write!(f, "longpress.right"),
+                LongPressEvent::Middle => write!(f, "longpress.middle"),
+                LongPressEvent::Other(n) => write!(f, "longpress.{n}")
+            },
+            EventTy::Mutation(_) => write!(f, "mutation"),
+            EventTy::Custom(name) => write!(f, "{name}"),
+            EventTy::Any(_) => write!(f, "any")
+        }
+    }
+}
+impl EventTy {
+    pub(crate) fn bubbles(&self) -> bool {
+        match self {
+            EventTy::Mouse(MouseEvent::Enter | MouseEvent::Leave) => false,
+            EventTy::Window(_) => false,
+            EventTy::Any(ty) => ty.bubbles(),
+            _ => true
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Phase {
+    Capture,
+    Target,
+    Bubble
+}
 
 
 #[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
 pub struct Event {
+    pub(crate) id: Xid,
     pub(crate) timestamp: u128,
     pub(crate) ty: EventTy,
     pub(crate) state: State,
     pub(crate) prev: State,
     pub(crate) target: Element,
+    pub(crate) current: Element,
+    pub(crate) phase: Phase,
     pub(crate) src: Element,
     pub(crate) extra: Json
 }
 impl Event {
     pub fn new(ty: EventTy, src: Element, extra: Json) -> Event {
         Event {
-            timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_millis(),
+            id: Engine::event_id(0),
+            timestamp: Engine::now(0),
             ty,
             state: instance!(0).state.clone(),
             prev: instance!(0).state.clone(),
             target: Element { xid: 0, iid: 0 },
+            current: Element { xid: 0, iid: 0 },
+            phase: Phase::Target,
             src,
             extra
         }
     }
+    pub fn target(&self) -> Element {
+        self.target
+    }
+    pub fn current_target(&self) -> Element {
+        self.current
+    }
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+    // stops propagation once the handlers on the current element have run
     pub fn halt(&self) {
-        // instance!(id).halted_events.insert(self.id.clone());
+        instance!(self.target.iid).halted_events.insert(self.id);
+    }
+    // stops propagation, skipping the rest of the handlers on the current element too
+    pub fn halt_immediately(&self) {
+        instance!(self.target.iid).immediately_halted_events.insert(self.id);
+    }
+    // skips the engine's own behaviour for the event, e.g. focusing whatever's clicked
+    pub fn prevent_default(&self) {
+        instance!(self.target.iid).prevented_events.insert(self.id);
+    }
+    pub fn default_prevented(&self) -> bool {
+        instance!(self.target.iid).prevented_events.contains(&self.id)
+    }
+    // replaces the payload of the drag in progress, e.g. from a `drag.start` handler
+    pub fn set_drag_payload(&self, payload: Json) {
+        instance!(self.target.iid).drag.as_mut().map(|drag| drag.payload = payload);
+    }
+}
+
+// something fed in by the host, see `Engine::input`
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) enum Input {
+    PointerMove(lyon::math::Point),
+    PointerDown(usize, lyon::math::Point),
+    PointerUp(usize, lyon::math::Point),
+    Scroll(lyon::math::Vector, ScrollUnit),
+    DropPayload(lyon::math::Point, Json),
+    KeyDown(String, ModifiersState),
+    KeyUp(String, ModifiersState),
+    SetModifiers(ModifiersState),
+    Resize(f32, f32),
+    SetFullscreen(bool),
+    Trigger(String, Xid, Json),
+    Advance
+}
+
+// an input, when it was fed in and the state it left behind, enough to reproduce it in another instance and check it went the same way
+#[derive(Clone)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub(crate) struct Record {
+    pub(crate) timestamp: u128,
+    pub(crate) input: Input,
+    pub(crate) state: State
+}
+
+pub(crate) const REQUIREMENTS: [&str; 7] = ["ctrl", "alt", "shift", "super", "outside", "this", "long"];
+pub(crate) const INDICATORS: [&str; 5] = ["bubble", "trickle", "default", "halt", "prevent"];
+
+#[derive(Clone)]
+pub(crate) struct Listener {
+    // the name it was registered with, e.g. `click.left.ctrl.halt`, and that with the modifiers stripped back off
+    pub(crate) event: String,
+    pub(crate) action: String,
+    pub(crate) selector: Option<String>,
+    pub(crate) handler: String,
+    pub(crate) requirements: std::collections::HashSet<String>,
+    pub(crate) indicators: std::collections::HashSet<String>
+}
+impl Listener {
+    pub(crate) fn new(event: &str, selector: Option<String>, handler: &str) -> Listener {
+        // the action always comes first, so it's never mistaken for a modifier, e.g. a custom `halt` event
+        let mut segments = event.split('.');
+        let first = segments.next().unwrap_or_default();
+        
+        let requirements = segments.clone().filter(|s| REQUIREMENTS.contains(s)).map(String::from).collect::<std::collections::HashSet<_>>();
+        let indicators = segments.clone().filter(|s| INDICATORS.contains(s)).map(String::from).collect::<std::collections::HashSet<_>>();
+        
+        let action =
+            std::iter::once(first)
+                .chain(segments.filter(|s| !requirements.contains(*s) && !indicators.contains(*s)))
+                .collect::<Vec<_>>()
+                .join(".");
+        
+        Listener { event: event.into(), action, selector, handler: handler.into(), requirements, indicators }
+    }
+    
+    pub(crate) fn accepts(&self, event: &Event) -> bool {
+        let requires = |requirement: &str| self.requirements.contains(requirement);
+        
+        // one without a selector belongs to the whole instance, and would otherwise hear the same event at every hop
+        let phase = if requires("this") || requires("outside") || self.selector.is_none() {
+            event.phase == Phase::Target
+        } else if self.indicators.contains("trickle") {
+            event.phase != Phase::Bubble
+        } else {
+            event.phase != Phase::Capture
+        };
+        
+        let long = event.extra.get("long").map(|long| long.as_bool()).flatten().unwrap_or_default();
+        
+        phase
+        && (!requires("ctrl") || event.state.control_key_pressed())
+        && (!requires("alt") || event.state.alt_key_pressed())
+        && (!requires("shift") || event.state.shift_key_pressed())
+        && (!requires("super") || event.state.super_key_pressed())
+        // a click that was a long press is only for those who ask for it, everyone else had `longpress`
+        && (requires("long") == long)
+        && (!self.indicators.contains("default") || !event.default_prevented())
+    }
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Press {
+    pub(crate) button: usize,
+    pub(crate) timestamp: u128,
+    pub(crate) position: lyon::math::Point,
+    pub(crate) target: Xid,
+    // whether it's been held long enough to have already fired `longpress`
+    pub(crate) long: bool
+}
+
+#[derive(Clone)]
+pub(crate) struct Drag {
+    pub(crate) source: Xid,
+    pub(crate) payload: Json,
+    pub(crate) origin: lyon::math::Point,
+    pub(crate) over: Option<Xid>,
+    pub(crate) active: bool
+}
+impl Drag {
+    pub(crate) fn extra(&self, position: lyon::math::Point) -> Json {
+        serde_json::json!({
+            "payload": self.payload,
+            "source": self.source,
+            "x": position.x,
+            "y": position.y
+        })
+    }
+}
+
+// times are in ms, distances in px
+#[derive(Clone, Copy)]
+pub struct PointerThresholds {
+    pub double_click_time: u128,
+    pub double_click_distance: f32,
+    pub long_press_time: u128,
+    pub drag_distance: f32
+}
+impl Default for PointerThresholds {
+    fn default() -> Self {
+        Self {
+            double_click_time: 500,
+            double_click_distance: 4.0,
+            long_press_time: 500,
+            drag_distance: 4.0
+        }
     }
 }
 
@@ -129,8 +478,9 @@ impl From<&str> for Handler {
 
 impl<T: Fn(Event) + Send + Sync + 'static>  From<T> for Handler {
     fn from(value: T) -> Self {
-        let name = Engine::xid(1).to_string();
+        let name = (0..Xid::MAX).map(|n| n.to_string()).find(|n| !instance!(1).handlers.contains_key(n)).unwrap_or_default();
         instance!(1).handlers.insert(name.clone(), std::sync::Arc::new(std::sync::Mutex::new(value)));
+        instance!(1).anonymous_handlers.insert(name.clone(), 0);
         Self(name)
     }
 }
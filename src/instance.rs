@@ -1,13 +1,13 @@
 use crate::{
-    engine::Engine,
+    engine::{self, Clock, Engine, ModifiersState},
     element::ElementX,
-    events::Event,
+    events::{self, Event, Input, PointerThresholds},
     macros::instance,
     node::{Node, Vertex},
     style::Style,
     wit::{
         traits::{GuestInstance, GuestValue},
-        types::{Element, InstanceId, Value}
+        types::{Element, InstanceId, Modifiers, ScrollUnit, SelectorError, Value}
     },
     element
 };
@@ -65,6 +65,7 @@ impl GuestInstance for InstanceX {
                     attributes: props,
                     ..Default::default()
                 });
+                instance!(self.0).stylesheet_dirty = true;
                 ElementX { xid, iid: self.0 }
             }
         };
@@ -90,12 +91,114 @@ impl GuestInstance for InstanceX {
     //         .collect()
     // }
     
-    // pub fn remove_event_handler(&self) -> Option<std::rc::Rc<dyn Fn(Event)>> {
-    //     todo!()
-    // }
+    fn add_event_listener(&self, selector: Option<String>, event: String, handler: String) -> bool {
+        // returns whether the selector could be parsed
+        Engine::listen(self.0, &event, selector, &handler)
+    }
+    
+    fn remove_event_listener(&self, selector: Option<String>, event: String, handler: String) {
+        Engine::unlisten(self.0, selector.as_deref(), Some(&event), Some(&handler));
+    }
+    
+    fn remove_event_handler(&self, handler: String) -> bool {
+        Engine::remove_handler(self.0, &handler)
+    }
+
+    fn pointer_move(&self, x: f32, y: f32) {
+        Engine::input(self.0, Input::PointerMove(lyon::math::point(x, y)));
+    }
+    
+    fn pointer_down(&self, button: u32, x: f32, y: f32) {
+        Engine::input(self.0, Input::PointerDown(button as usize, lyon::math::point(x, y)));
+    }
+    
+    fn pointer_up(&self, button: u32, x: f32, y: f32) {
+        Engine::input(self.0, Input::PointerUp(button as usize, lyon::math::point(x, y)));
+    }
+    
+    fn resize(&self, width: f32, height: f32) {
+        Engine::input(self.0, Input::Resize(width, height));
+    }
+    
+    fn set_fullscreen(&self, fullscreen: bool) {
+        Engine::input(self.0, Input::SetFullscreen(fullscreen));
+    }
+    
+    fn set_modifiers(&self, modifiers: Modifiers) {
+        Engine::input(self.0, Input::SetModifiers(modifiers_state(modifiers)));
+    }
+    
+    // keys are named as in a browser's `KeyboardEvent.key`, e.g. `Tab`, returns whether the event was cancelled
+    fn key_down(&self, key: String, modifiers: Modifiers) -> bool {
+        Engine::input(self.0, Input::KeyDown(key, modifiers_state(modifiers)))
+    }
+    
+    fn key_up(&self, key: String, modifiers: Modifiers) -> bool {
+        Engine::input(self.0, Input::KeyUp(key, modifiers_state(modifiers)))
+    }
+    
+    fn scroll(&self, dx: f32, dy: f32, unit: ScrollUnit) {
+        let unit = match unit {
+            ScrollUnit::Pixels => events::ScrollUnit::Pixels,
+            ScrollUnit::Lines => events::ScrollUnit::Lines
+        };
+        Engine::input(self.0, Input::Scroll(lyon::math::vector(dx, dy), unit));
+    }
+    
+    fn set_pointer_thresholds(&self, double_click_time: u32, double_click_distance: f32, long_press_time: u32, drag_distance: f32) {
+        instance!(self.0).pointer_thresholds = PointerThresholds {
+            double_click_time: double_click_time as u128,
+            double_click_distance,
+            long_press_time: long_press_time as u128,
+            drag_distance
+        };
+    }
+    
+    fn drop_payload(&self, x: f32, y: f32, payload: Value) {
+        Engine::input(self.0, Input::DropPayload(lyon::math::point(x, y), payload.json()));
+    }
+    
+    fn trigger(&self, event: String, target: Element, extra: Value) -> bool {
+        // returns whether the event was cancelled
+        Engine::input(self.0, Input::Trigger(event, target.id, extra.json()))
+    }
 
-    fn trigger(&self) {
-        todo!() // return bool indicating if event was cancelled?
+    fn set_clock(&self, time: Option<u64>) {
+        // a host without a clock the engine can read sets the time every frame, `none` goes back to the default,
+        // which is the system clock wherever there is one
+        let clock = time.map(|time| Clock::Manual(time as u128)).unwrap_or_default();
+        Engine::set_clock(self.0, clock);
+    }
+    
+    fn advance_clock(&self, delta: u64) {
+        Engine::advance_clock(self.0, delta as u128);
+    }
+    
+    fn advance(&self) -> bool {
+        // returns whether another frame is needed, hosts can sleep until the next input otherwise
+        Engine::input(self.0, Input::Advance)
+    }
+    
+    fn start_recording(&self) {
+        Engine::start_recording(self.0);
+    }
+    
+    fn stop_recording(&self) -> Result<String, String> {
+        serde_json::to_string(&Engine::stop_recording(self.0)).map_err(|e| e.to_string())
+    }
+    
+    fn replay(&self, log: String) -> Result<Option<u32>, String> {
+        // returns the first record that didn't leave the state as it was recorded, if any
+        let records = serde_json::from_str(&log).map_err(|e| e.to_string())?;
+        Ok(Engine::replay(self.0, records).map(|idx| idx as u32))
+    }
+    
+    fn observe(&self, root: Element, handler: String, subtree: bool) -> u64 {
+        Engine::observe(self.0, root.id, handler, subtree)
+    }
+    
+    fn unobserve(&self, observer: u64) -> bool {
+        Engine::unobserve(self.0, observer)
     }
 
     fn select(&self, selector: String) -> Vec<Element> {
@@ -106,6 +209,12 @@ impl GuestInstance for InstanceX {
         Engine::select(self.0, &selector).first().copied().map(Element::new)
     }
     
+    fn try_select(&self, selector: String) -> Result<Vec<Element>, SelectorError> {
+        Engine::try_select(self.0, &selector)
+            .map(|elements| elements.into_iter().map(Element::new).collect())
+            .map_err(|e| SelectorError { offset: e.offset as u32, expected: e.expected, message: e.message })
+    }
+    
     // pub fn render(&self, root: Element) -> (Vec<Vertex>, Vec<u16>) {
     //     let buffers = Engine::render(self.0, root.xid);
     //     (buffers.vertices, buffers.indices)
@@ -115,3 +224,15 @@ impl GuestInstance for InstanceX {
         crate::ENGINES.get_or_init(|| Default::default()).remove(&self.0);
     }
 }
+
+fn modifiers_state(modifiers: Modifiers) -> ModifiersState {
+    [
+        (Modifiers::CTRL, engine::Modifiers::Control),
+        (Modifiers::ALT, engine::Modifiers::Alt),
+        (Modifiers::SHIFT, engine::Modifiers::Shift),
+        (Modifiers::SUPER, engine::Modifiers::Super)
+    ]
+    .into_iter()
+    .filter(|(flag, _)| modifiers.contains(*flag))
+    .fold(0, |state, (_, modifier)| state | (modifier as ModifiersState))
+}
@@ -1,7 +1,8 @@
 use crate::{
     Json, Xid,
     macros::instance,
-    style::Style
+    style::Style,
+    text::LINE_HEIGHT
 };
 
 #[derive(Default)]
@@ -34,9 +35,9 @@ pub struct Vertex {
 }
 
 impl Node {
-    pub(crate) fn is_within(&self, point: lyon::math::Point) -> bool {
+    // takes the layout rather than looking it up, so it can be called while the instance is borrowed
+    pub(crate) fn is_within(layout: &taffy::prelude::Layout, point: lyon::math::Point) -> bool {
         // let inv_pos = self.model().inverse().transform_point3(Vec3::new(pos.x, pos.y, 0.0));
-        let layout = instance!(1).layout.layout(self.layout_id).copied().unwrap();
         // TODO: account for border radius
         (point.x > layout.location.x.into())
         && (point.x < (layout.location.x + layout.size.width).into())
@@ -44,7 +45,7 @@ impl Node {
         && (point.y < (layout.location.y + layout.size.height).into())
     }
     
-    pub(crate) fn render(&self) -> lyon::tessellation::VertexBuffers<Vertex, u16> {
+    pub(crate) fn render(&self, scroll: lyon::math::Vector) -> lyon::tessellation::VertexBuffers<Vertex, u16> {
         let layout = instance!(1).layout.layout(self.layout_id).copied().unwrap();
         
         let mut geometry = lyon::tessellation::VertexBuffers::new();
@@ -58,8 +59,8 @@ impl Node {
             builder.add_rounded_rectangle(
                 &lyon::math::Box2D::from_origin_and_size(
                     lyon::math::point(
-                        layout.location.x - self.scroll_offset.x,
-                        layout.location.y - self.scroll_offset.y,
+                        layout.location.x - scroll.x,
+                        layout.location.y - scroll.y,
                     ),
                     lyon::math::size(layout.size.width, layout.size.height),
                 ),
@@ -93,7 +94,7 @@ impl Node {
             
             let mut buffer = cosmic_text::Buffer::new(
                 font_system,
-                cosmic_text::Metrics::new(self.style.text.size, 20.0)
+                cosmic_text::Metrics::new(self.style.text.size, LINE_HEIGHT)
             );
             buffer.set_size(font_system, layout.size.width, layout.size.height);
             buffer.set_text(
@@ -115,8 +116,8 @@ impl Node {
                     builder.add_rectangle(
                         &lyon::math::Box2D::from_origin_and_size(
                             lyon::math::point(
-                                layout.location.x - self.scroll_offset.x + x as f32,
-                                layout.location.y - self.scroll_offset.y + y as f32,
+                                layout.location.x - scroll.x + x as f32,
+                                layout.location.y - scroll.y + y as f32,
                             ),
                             lyon::math::size(w as f32, h as f32),
                         ),
@@ -1,24 +1,56 @@
+// where a selector stopped making sense, and what would have
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectorError {
+    // in bytes, from the start of the selector
+    pub offset: usize,
+    pub expected: String,
+    pub message: String
+}
+
+impl SelectorError {
+    fn new(offset: usize, expected: &str, message: impl Into<String>) -> SelectorError {
+        SelectorError { offset, expected: expected.into(), message: message.into() }
+    }
+    
+    // for errors found parsing part of a bigger selector, e.g. the arguments to `:is()`
+    pub(crate) fn offset_by(self, offset: usize) -> SelectorError {
+        SelectorError { offset: self.offset + offset, ..self }
+    }
+}
+
+impl std::fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (at {}, expected {})", self.message, self.offset, self.expected)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+// `None` when what's there is something else entirely, an error when it's the right thing but malformed
+type Parsed<T> = Result<(Option<T>, (usize, usize)), SelectorError>;
+
+// the character at `pos`, for error messages
+fn found(src: &str, pos: usize) -> String {
+    src[pos..].chars().next().map(|c| format!("found `{c}`")).unwrap_or("found the end of the selector".into())
+}
+
+
 pub(crate) struct Xid(pub(crate) (usize, usize));
 
 impl Xid {
-    fn parse(src: &str, range: (usize, usize)) -> (Option<Xid>, (usize, usize)) {
+    fn parse(src: &str, range: (usize, usize)) -> Parsed<Xid> {
         if !(&src[range.0..range.1]).chars().next().map(|c| c == '%').unwrap_or_default() {
-            return (None, range);
+            return Ok((None, range));
         }
         
-        let pos = (&src[(range.0 + 1)..range.1]).chars().position(|c| !c.is_digit(10));
-        
-        if let Some(pos) = pos {
-            if pos == 0 {
-                (None, range)
-            } else {
-                let xid = ((range.0 + 1), (range.0 + pos));
-                let rest = ((range.0 + pos), range.1);
-                (Some(Xid(xid)), rest)
-            }
-        } else {
-            (Some(Xid(((range.0 + 1), range.1))), (range.1, src.chars().count()))
+        let len = (&src[(range.0 + 1)..range.1]).find(|c: char| !c.is_ascii_digit()).unwrap_or(range.1 - (range.0 + 1));
+        if len == 0 {
+            return Err(SelectorError::new((range.0 + 1), "an element's xid", found(src, (range.0 + 1))));
         }
+        
+        let xid = ((range.0 + 1), (range.0 + 1 + len));
+        let rest = ((range.0 + 1 + len), range.1);
+        Ok((Some(Xid(xid)), rest))
     }
 }
 
@@ -27,19 +59,16 @@ pub(crate) struct Ident(pub(crate) (usize, usize));
 
 impl Ident {
     fn parse(src: &str, range: (usize, usize)) -> (Option<Ident>, (usize, usize)) {
-        if (&src[range.0..range.1]).chars().next().map(|c| (c == '_') || c.is_alphabetic()).unwrap_or_default() {
-            let pos = (&src[(range.0 + 1)..range.1]).chars().position(|c| !(c.is_alphabetic() || c.is_digit(10) || (c == '_')));
+        if let Some(first) = (&src[range.0..range.1]).chars().next().filter(|c| (*c == '_') || c.is_alphabetic()) {
+            let start = range.0 + first.len_utf8();
+            let pos = (&src[start..range.1]).find(|c: char| !(c.is_alphabetic() || c.is_digit(10) || (c == '_')));
             
             if let Some(pos) = pos {
-                if pos == 0 {
-                    (Some(Ident((range.0, (range.0 + 1)))), ((range.0 + 1), range.1))
-                } else {
-                    let ident = (range.0, (range.0 + pos + 1));
-                    let rest = ((range.0 + pos + 1), range.1);
-                    (Some(Ident(ident)), rest)
-                }
+                let ident = (range.0, (start + pos));
+                let rest = ((start + pos), range.1);
+                (Some(Ident(ident)), rest)
             } else {
-                (Some(Ident(range)), (range.1, src.chars().count()))
+                (Some(Ident(range)), (range.1, range.1))
             }
         } else {
             (None, range)
@@ -54,102 +83,430 @@ pub(crate) enum Op {
     NotEquals,
     StartsWith,
     Contains,
-    EndsWith
+    EndsWith,
+    // `~=`, one of the whitespace separated words
+    Includes,
+    // `|=`, either exactly or followed by `-`, as in `lang|=en` for `en-GB`
+    DashMatch
 }
 
 pub(crate) struct Attribute {
     pub(crate) name: Ident,
     pub(crate) op: Op,
-    pub(crate) value: Option<(usize, usize)>
+    // with any quotes and escapes already taken care of, so unlike everything else not a range
+    pub(crate) value: Option<String>,
+    // the `i` flag, as in `[type="submit" i]`
+    pub(crate) case_insensitive: bool
 }
 
 impl Attribute {
-    fn parse(src: &str, range: (usize, usize)) -> (Option<Attribute>, (usize, usize)) {
+    fn parse(src: &str, range: (usize, usize)) -> Parsed<Attribute> {
         if !(&src[range.0..range.1]).chars().next().map(|c| c == '[').unwrap_or_default() {
-            return (None, range);
+            return Ok((None, range));
         }
         
-        let (Some(ident), rest) = Ident::parse(src, ((range.0 + 1), range.1)) else {
-            return (None, range);
+        // whitespace is allowed around everything within the brackets
+        let skip = |pos: usize| pos + ((&src[pos..range.1]).len() - (&src[pos..range.1]).trim_start().len());
+        let close = |pos: usize, attribute: Attribute| {
+            if (&src[pos..range.1]).chars().next().map(|c| c == ']').unwrap_or_default() {
+                Ok((Some(attribute), ((pos + 1), range.1)))
+            } else if pos == range.1 {
+                Err(SelectorError::new(range.1, "`]`", format!("attribute opened at {} is never closed", range.0)))
+            } else {
+                Err(SelectorError::new(pos, "`]`", found(src, pos)))
+            }
+        };
+        
+        let (Some(ident), rest) = Ident::parse(src, (skip(range.0 + 1), range.1)) else {
+            return Err(SelectorError::new(skip(range.0 + 1), "an attribute name", found(src, skip(range.0 + 1))));
         };
+        let rest = (skip(rest.0), rest.1);
         
         if (&src[rest.0..rest.1]).chars().next().map(|c| c == ']').unwrap_or_default() {
-            return (
-                Some(
-                    Attribute {
-                        name: ident,
-                        op: Op::Exists,
-                        value: None
-                    }
-                ),
-                ((rest.0 + 1), rest.1)
-            );
+            return close(rest.0, Attribute { name: ident, op: Op::Exists, value: None, case_insensitive: false });
         }
         
         let op = match (&src[rest.0..rest.1]).chars().next() {
-            Some(c) => match c {
-                '=' => Op::Equals,
-                eqmod => {
-                    if !(&src[(rest.0 + 1)..rest.1]).chars().next().map(|c| c == '=').unwrap_or_default() {
-                        return (None, range);
-                    }
-                    
-                    match eqmod {
-                        '!' => Op::NotEquals,
-                        '^' => Op::StartsWith,
-                        '*' => Op::Contains,
-                        '$' => Op::EndsWith,
-                        _ => return (None, range)
-                    }
+            Some('=') => Op::Equals,
+            Some(eqmod @ ('!' | '^' | '*' | '$' | '~' | '|')) => {
+                if !(&src[(rest.0 + 1)..rest.1]).chars().next().map(|c| c == '=').unwrap_or_default() {
+                    return Err(SelectorError::new((rest.0 + 1), "`=`", format!("{} after `{eqmod}`", found(src, (rest.0 + 1)))));
+                }
+                
+                match eqmod {
+                    '!' => Op::NotEquals,
+                    '^' => Op::StartsWith,
+                    '*' => Op::Contains,
+                    '$' => Op::EndsWith,
+                    '~' => Op::Includes,
+                    _ => Op::DashMatch
                 }
             },
-            None => return (None, range)
+            _ => return Err(SelectorError::new(rest.0, "`]` or an operator like `=`", found(src, rest.0)))
         };
         
         let start_pos = match op {
             Op::Equals => 1,
             _ => 2
         };
-        let end_pos = (&src[(rest.0 + start_pos)..rest.1]).chars().position(|c| c == ']');
-        if let Some(end_pos) = end_pos {
-            if end_pos == 0 {
-                (None, range)
-            } else {
-                let value = ((rest.0 + start_pos), (rest.0 + start_pos + end_pos));
-                (
-                    Some(
-                        Attribute {
-                            name: ident,
-                            op,
-                            value: Some(value)
-                        }
-                    ),
-                    ((rest.0 + start_pos + end_pos + 1), rest.1)
-                )
+        let start = skip(rest.0 + start_pos);
+        
+        let (value, end) = match (&src[start..range.1]).chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let (value, end) = unescape(src, ((start + 1), range.1), |c| c == quote);
+                if end == range.1 {
+                    return Err(SelectorError::new(range.1, &format!("`{quote}`"), format!("string opened at {start} is never closed")));
+                }
+                (value, (end + 1))
+            },
+            _ => {
+                let (value, end) = unescape(src, (start, range.1), |c| c.is_whitespace() || (c == ']'));
+                if value.is_empty() {
+                    return Err(SelectorError::new(start, "a value", "attribute has an operator but no value to compare with"));
+                }
+                (value, end)
             }
+        };
+        
+        // the `i` and `s` flags, matching case-insensitively and sensitively (the default)
+        let end = skip(end);
+        let (case_insensitive, end) = match (&src[end..range.1]).chars().next() {
+            Some('i' | 'I') => (true, skip(end + 1)),
+            Some('s' | 'S') => (false, skip(end + 1)),
+            _ => (false, end)
+        };
+        
+        close(end, Attribute { name: ident, op, value: Some(value), case_insensitive })
+    }
+    
+    // whether an element's value for the attribute matches
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        let Some(expected) = &self.value else {
+            return true;
+        };
+        
+        let (value, expected) = if self.case_insensitive {
+            (value.to_lowercase(), expected.to_lowercase())
         } else {
-            (None, range)
+            (value.to_string(), expected.clone())
+        };
+        
+        match self.op {
+            Op::Exists => true,
+            Op::Equals => value == expected,
+            Op::NotEquals => value != expected,
+            // like in CSS, an empty value can't be the start, middle or end of anything
+            Op::StartsWith => !expected.is_empty() && value.starts_with(&expected),
+            Op::Contains => !expected.is_empty() && value.contains(&expected),
+            Op::EndsWith => !expected.is_empty() && value.ends_with(&expected),
+            Op::Includes => value.split_whitespace().any(|word| word == expected),
+            Op::DashMatch => (value == expected) || value.starts_with(&format!("{expected}-"))
         }
     }
 }
 
+// reads from the start of `range` up to the first unescaped character `end` matches (or the end of the range),
+// decoding CSS escapes on the way, i.e. `\` followed by either up to 6 hex digits and optionally a space, or any other character
+fn unescape(src: &str, range: (usize, usize), end: impl Fn(char) -> bool) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = (&src[range.0..range.1]).char_indices().peekable();
+    
+    while let Some((pos, c)) = chars.next() {
+        if c != '\\' {
+            if end(c) {
+                return (value, (range.0 + pos));
+            }
+            value.push(c);
+            continue;
+        }
+        
+        let mut hex = String::new();
+        while let Some((_, c)) = chars.peek().filter(|(_, c)| c.is_ascii_hexdigit() && (hex.len() < 6)) {
+            hex.push(*c);
+            chars.next();
+        }
+        
+        if hex.is_empty() {
+            match chars.next() {
+                // an escaped newline just continues the string
+                Some((_, '\n')) => {},
+                Some((_, c)) => value.push(c),
+                None => value.push(char::REPLACEMENT_CHARACTER)
+            }
+        } else {
+            let code = u32::from_str_radix(&hex, 16).unwrap_or_default();
+            value.push(char::from_u32(code).filter(|c| *c != '\0').unwrap_or(char::REPLACEMENT_CHARACTER));
+            chars.next_if(|(_, c)| c.is_whitespace());
+        }
+    }
+    
+    (value, range.1)
+}
+
+// the characters of `src` that aren't within quotes or escaped, so a `,`, `(` or `)` in an attribute's value is left alone
+fn unquoted(src: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut escaped = false;
+    src.char_indices().filter(move |(_, c)| {
+        if escaped {
+            escaped = false;
+            return false;
+        }
+        match (*c, quote) {
+            ('\\', _) => {
+                escaped = true;
+                false
+            },
+            (c, Some(q)) => {
+                if c == q {
+                    quote = None;
+                }
+                false
+            },
+            ('"' | '\'', None) => {
+                quote = Some(*c);
+                false
+            },
+            _ => true
+        }
+    })
+}
+
+
+// the comma separated parts of a selector list, leaving alone any commas within brackets, e.g. `:is(a, b), c`
+// along with where each one starts
+pub(crate) fn split_list(src: &str) -> Vec<(usize, &str)> {
+    let mut parts = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let part = |start: usize, end: usize| {
+        let untrimmed = &src[start..end];
+        (start + (untrimmed.len() - untrimmed.trim_start().len()), untrimmed.trim())
+    };
+    for (pos, c) in unquoted(src) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(part(start, pos));
+                start = pos + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push(part(start, src.len()));
+    parts
+}
+
+// checks every part of the selector list in `range` parses
+fn parse_list(src: &str, range: (usize, usize)) -> Result<(), SelectorError> {
+    for (offset, part) in split_list(&src[range.0..range.1]) {
+        Selector::parse(std::borrow::Cow::Borrowed(part)).map_err(|e| e.offset_by(range.0 + offset))?;
+    }
+    Ok(())
+}
+
+// a selector relative to some element, as `:has()` takes, starting with the link to follow from it (by default, to its descendants)
+pub(crate) fn relative(src: &str) -> Result<(Link, Selector), SelectorError> {
+    let (link, rest) = match Link::parse(src, (0, src.len())) {
+        (Some(link), rest) => (link, rest),
+        (None, _) => (Link::Descendants, (0, src.len()))
+    };
+    Selector::parse(std::borrow::Cow::Borrowed(&src[rest.0..rest.1]))
+        .map(|selector| (link, selector))
+        .map_err(|e| e.offset_by(rest.0))
+}
+
+// the range between a pair of brackets at the start of `range`, allowing for any nested inside, and what follows them
+fn arguments(src: &str, range: (usize, usize)) -> Result<Option<((usize, usize), (usize, usize))>, SelectorError> {
+    if !(&src[range.0..range.1]).chars().next().map(|c| c == '(').unwrap_or_default() {
+        return Ok(None);
+    }
+    
+    let mut depth = 0;
+    for (pos, c) in unquoted(&src[range.0..range.1]) {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Ok(Some((((range.0 + 1), (range.0 + pos)), ((range.0 + pos + 1), range.1))));
+        }
+    }
+    
+    Err(SelectorError::new(range.1, "`)`", format!("bracket opened at {} is never closed", range.0)))
+}
+
+
+// `an+b`, matching the elements at every `a`th position from the `b`th, optionally counting only those matching `of S`
+pub(crate) struct Nth {
+    pub(crate) a: i64,
+    pub(crate) b: i64,
+    pub(crate) of: Option<(usize, usize)>
+}
+
+impl Nth {
+    fn parse(src: &str, range: (usize, usize)) -> Result<Nth, SelectorError> {
+        let args = &src[range.0..range.1];
+        
+        let (formula, of) = match args.find(" of ") {
+            Some(pos) => {
+                let of = ((range.0 + pos + 4), range.1);
+                parse_list(src, of)?;
+                (&args[..pos], Some(of))
+            },
+            None => (args, None)
+        };
+        
+        let invalid = || SelectorError::new(range.0, "`an+b`, `odd` or `even`", format!("`{}` isn't a valid position", formula.trim()));
+        
+        let formula = formula.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+        let (a, b) = match formula.as_str() {
+            "odd" => (2, 1),
+            "even" => (2, 0),
+            formula => match formula.split_once('n') {
+                Some((a, b)) => {
+                    let a = match a {
+                        "" | "+" => 1,
+                        "-" => -1,
+                        a => a.parse().map_err(|_| invalid())?
+                    };
+                    let b = if b.is_empty() {
+                        0
+                    } else if b.starts_with(['+', '-']) {
+                        b.parse().map_err(|_| invalid())?
+                    } else {
+                        return Err(invalid());
+                    };
+                    (a, b)
+                },
+                None => (0, formula.parse().map_err(|_| invalid())?)
+            }
+        };
+        
+        Ok(Nth { a, b, of: of.map(|of| (of.0 + (src[of.0..of.1].len() - src[of.0..of.1].trim_start().len()), of.1)) })
+    }
+    
+    // whether some `n` of 0 or more puts an element at `idx`, counting from 1
+    pub(crate) fn matches(&self, idx: i64) -> bool {
+        if self.a == 0 {
+            idx == self.b
+        } else {
+            ((idx - self.b) % self.a == 0) && ((idx - self.b) / self.a >= 0)
+        }
+    }
+}
+
+
+pub(crate) enum PseudoClass {
+    Hover,
+    Focus,
+    FocusWithin,
+    Active,
+    Disabled,
+    FirstChild,
+    LastChild,
+    OnlyChild,
+    Empty,
+    Root,
+    NthChild(Nth),
+    NthLastChild(Nth),
+    NthOfType(Nth),
+    NthLastOfType(Nth),
+    // each a selector list, or a relative one for `Has`
+    Not((usize, usize)),
+    Is((usize, usize)),
+    Where((usize, usize)),
+    Has((usize, usize))
+}
+
+impl PseudoClass {
+    fn parse(src: &str, range: (usize, usize)) -> Parsed<PseudoClass> {
+        if !(&src[range.0..range.1]).chars().next().map(|c| c == ':').unwrap_or_default() {
+            return Ok((None, range));
+        }
+        
+        let len = (&src[(range.0 + 1)..range.1]).find(|c: char| !(c.is_alphanumeric() || (c == '-'))).unwrap_or(range.1 - (range.0 + 1));
+        let name = &src[(range.0 + 1)..(range.0 + 1 + len)];
+        let rest = ((range.0 + 1 + len), range.1);
+        
+        if name.is_empty() {
+            return Err(SelectorError::new((range.0 + 1), "a pseudo-class", found(src, (range.0 + 1))));
+        }
+        let unknown = |suffix: &str| SelectorError::new((range.0 + 1), "a pseudo-class", format!("`:{name}{suffix}` isn't a pseudo-class"));
+        
+        if let Some((args, rest)) = arguments(src, rest)? {
+            let pseudo_class = match name {
+                "not" | "is" | "where" => {
+                    parse_list(src, args)?;
+                    match name {
+                        "not" => PseudoClass::Not(args),
+                        "is" => PseudoClass::Is(args),
+                        _ => PseudoClass::Where(args)
+                    }
+                },
+                "has" => {
+                    for (offset, part) in split_list(&src[args.0..args.1]) {
+                        relative(part).map_err(|e| e.offset_by(args.0 + offset))?;
+                    }
+                    PseudoClass::Has(args)
+                },
+                "nth-child" => PseudoClass::NthChild(Nth::parse(src, args)?),
+                "nth-last-child" => PseudoClass::NthLastChild(Nth::parse(src, args)?),
+                "nth-of-type" | "nth-last-of-type" => {
+                    let nth = Nth::parse(src, args)?;
+                    // `of S` only makes sense for the ones that don't already narrow down the siblings
+                    if let Some(of) = nth.of {
+                        return Err(SelectorError::new(of.0, "`)`", format!("`:{name}()` doesn't take `of`")));
+                    }
+                    if name == "nth-of-type" { PseudoClass::NthOfType(nth) } else { PseudoClass::NthLastOfType(nth) }
+                },
+                _ => return Err(unknown("()"))
+            };
+            
+            return Ok((Some(pseudo_class), rest));
+        }
+        
+        let pseudo_class = match name {
+            "hover" => PseudoClass::Hover,
+            "focus" => PseudoClass::Focus,
+            "focus-within" => PseudoClass::FocusWithin,
+            "active" => PseudoClass::Active,
+            "disabled" => PseudoClass::Disabled,
+            "first-child" => PseudoClass::FirstChild,
+            "last-child" => PseudoClass::LastChild,
+            "only-child" => PseudoClass::OnlyChild,
+            "empty" => PseudoClass::Empty,
+            "root" => PseudoClass::Root,
+            _ => return Err(unknown(""))
+        };
+        
+        Ok((Some(pseudo_class), rest))
+    }
+}
+
 
 pub(crate) struct Rule {
     pub(crate) tag: Option<Ident>,
     pub(crate) xid: Option<Xid>,
     pub(crate) id: Option<Ident>,
     pub(crate) classes: Vec<Ident>,
-    pub(crate) attributes: Vec<Attribute>
+    pub(crate) attributes: Vec<Attribute>,
+    pub(crate) pseudo_classes: Vec<PseudoClass>
 }
 
 impl Rule {
-    fn parse(src: &str, range: (usize, usize)) -> (Option<Rule>, (usize, usize)) {
+    fn parse(src: &str, range: (usize, usize)) -> Parsed<Rule> {
         let (tag, rest) = Ident::parse(src, range);
-        let (xid, rest) = Xid::parse(src, rest);
+        let (xid, rest) = Xid::parse(src, rest)?;
         
         let (id, rest) = {
             if (&src[rest.0..rest.1]).chars().next().map(|c| c == '#').unwrap_or_default() {
-                Ident::parse(src, ((rest.0 + 1), rest.1))
+                let (Some(id), rest) = Ident::parse(src, ((rest.0 + 1), rest.1)) else {
+                    return Err(SelectorError::new((rest.0 + 1), "an id", found(src, (rest.0 + 1))));
+                };
+                (Some(id), rest)
             } else {
                 (None, rest)
             }
@@ -164,7 +521,7 @@ impl Rule {
                     classes.push(class);
                     nxt = rest;
                 } else {
-                    return (None, range);
+                    return Err(SelectorError::new((nxt.0 + 1), "a class", found(src, (nxt.0 + 1))));
                 }
             }
             (classes, nxt)
@@ -172,17 +529,65 @@ impl Rule {
         
         let mut attributes = vec![];
         let mut rest = rest;
-        while let (Some(attribute), nxt) = Attribute::parse(src, rest) {
+        while let (Some(attribute), nxt) = Attribute::parse(src, rest)? {
             attributes.push(attribute);
             rest = nxt;
         }
         
-        (
+        let mut pseudo_classes = vec![];
+        while let (Some(pseudo_class), nxt) = PseudoClass::parse(src, rest)? {
+            pseudo_classes.push(pseudo_class);
+            rest = nxt;
+        }
+        
+        if tag.is_none() && xid.is_none() && id.is_none() && classes.is_empty() && attributes.is_empty() && pseudo_classes.is_empty() {
+            return Ok((None, range));
+        }
+        
+        Ok((
             Some(
-                Rule { tag, xid, id, classes, attributes }
+                Rule { tag, xid, id, classes, attributes, pseudo_classes }
             ),
             rest
-        )
+        ))
+    }
+    
+    // ids, then classes, attributes and pseudo-classes, then tags, as in CSS
+    fn specificity(&self, src: &str) -> (usize, usize, usize) {
+        let most_specific = |range: (usize, usize), relative_to: bool| {
+            split_list(&src[range.0..range.1])
+                .into_iter()
+                .filter_map(|(_, s)| {
+                    if relative_to {
+                        relative(s).ok().map(|(_, selector)| selector.specificity())
+                    } else {
+                        Selector::parse(std::borrow::Cow::Borrowed(s)).ok().map(|selector| selector.specificity())
+                    }
+                })
+                .max()
+                .unwrap_or_default()
+        };
+        
+        let mut specificity = (
+            self.id.iter().count() + self.xid.iter().count(),
+            self.classes.len() + self.attributes.len(),
+            self.tag.iter().count()
+        );
+        for pseudo_class in &self.pseudo_classes {
+            let (a, b, c) = match pseudo_class {
+                PseudoClass::Where(_) => (0, 0, 0),
+                PseudoClass::Not(range) | PseudoClass::Is(range) => most_specific(*range, false),
+                PseudoClass::Has(range) => most_specific(*range, true),
+                PseudoClass::NthChild(Nth { of: Some(of), .. }) | PseudoClass::NthLastChild(Nth { of: Some(of), .. }) => {
+                    let (a, b, c) = most_specific(*of, false);
+                    (a, b + 1, c)
+                },
+                _ => (0, 1, 0)
+            };
+            specificity = (specificity.0 + a, specificity.1 + b, specificity.2 + c);
+        }
+        
+        specificity
     }
 }
 
@@ -200,33 +605,31 @@ pub(crate) enum Link {
 }
 
 impl Link {
-    fn parse(src: &str, range: (usize, usize)) -> (Option<Link>, (usize, usize)) {
-        let Some(start_pos) = (&src[range.0..range.1]).chars().position(|c| !c.is_whitespace()) else {
+    pub(crate) fn parse(src: &str, range: (usize, usize)) -> (Option<Link>, (usize, usize)) {
+        let Some(start_pos) = (&src[range.0..range.1]).find(|c: char| !c.is_whitespace()) else {
             return (None, range);
         };
         
-        let (link, rest) = match (&src[(range.0 + start_pos)..range.1]).chars().next() {
+        let (link, len) = match (&src[(range.0 + start_pos)..range.1]).chars().next() {
             Some(c) => {
-                let rest = &src[(range.0 + start_pos + 1)..range.1];
-                let rest2 = &src[(range.0 + start_pos + 2)..range.1];
-                let c2 = rest.chars().next();
+                let c2 = (&src[(range.0 + start_pos + c.len_utf8())..range.1]).chars().next();
                 match c {
                     '<' => match c2 {
-                        Some('<') => (Link::Ancestors, rest2),
-                        _ => (Link::Parent, rest)
+                        Some('<') => (Link::Ancestors, 2),
+                        _ => (Link::Parent, 1)
                     },
                     '>' => match c2 {
-                        Some('>') => (Link::Descendants, rest2),
-                        _ => (Link::Children, rest)
+                        Some('>') => (Link::Descendants, 2),
+                        _ => (Link::Children, 1)
                     },
                     '+' => match c2 {
-                        Some('+') => (Link::NextSiblings, rest2),
-                        _ => (Link::NextSibling, rest)
+                        Some('+') => (Link::NextSiblings, 2),
+                        _ => (Link::NextSibling, 1)
                     },
                     '~' => match c2 {
-                        Some('~') => (Link::PrevSiblings, rest2),
-                        Some('+') => (Link::Siblings, rest2),
-                        _ => (Link::PrevSibling, rest)
+                        Some('~') => (Link::PrevSiblings, 2),
+                        Some('+') => (Link::Siblings, 2),
+                        _ => (Link::PrevSibling, 1)
                     },
                     _ => return (None, range)
                 }
@@ -234,11 +637,11 @@ impl Link {
             None => return (None, range)
         };
         
-        let Some(end_pos) = rest.chars().position(|c| !c.is_whitespace()) else {
-            return (None, range);
-        };
+        // the next rule starts after any whitespace following the link, not counting from before it
+        let rest = ((range.0 + start_pos + len), range.1);
+        let end_pos = (&src[rest.0..rest.1]).find(|c: char| !c.is_whitespace()).unwrap_or(rest.1 - rest.0);
         
-        (Some(link), ((range.0 + end_pos), range.1))
+        (Some(link), ((rest.0 + end_pos), rest.1))
     }
 }
 
@@ -249,25 +652,28 @@ pub(crate) struct Selector<'a> {
 }
 
 impl Selector<'_> {
-    pub(crate) fn parse(src: std::borrow::Cow<str>) -> Option<Selector> {
+    pub(crate) fn parse(src: std::borrow::Cow<str>) -> Result<Selector, SelectorError> {
         let mut rules = vec![];
-        let mut range = (0, src.chars().count());
+        let mut range = (0, src.len());
         
         loop {
-            let (Some(rule), nxt) = Rule::parse(src.as_ref(), range) else {
-                return None;
+            let (Some(rule), nxt) = Rule::parse(src.as_ref(), range)? else {
+                return Err(SelectorError::new(range.0, "a selector", found(src.as_ref(), range.0)));
             };
             
             if let (Some(link), nxt) = Link::parse(src.as_ref(), nxt) {
                 rules.push((rule, Some(link)));
                 range = nxt;
-            } else {
+            } else if src[nxt.0..nxt.1].trim().is_empty() {
                 rules.push((rule, None));
-                return Some(
+                return Ok(
                     Selector {
                         rules, src
                     }
                 );
+            } else {
+                let pos = nxt.0 + (src[nxt.0..nxt.1].len() - src[nxt.0..nxt.1].trim_start().len());
+                return Err(SelectorError::new(pos, "a link, like `>`, or the end of the selector", found(src.as_ref(), pos)));
             }
         }
     }
@@ -275,4 +681,240 @@ impl Selector<'_> {
     pub(crate) fn get(&self, range: (usize, usize)) -> &str {
         &self.src[range.0..range.1]
     }
+    
+    pub(crate) fn specificity(&self) -> (usize, usize, usize) {
+        self
+            .rules
+            .iter()
+            .map(|(rule, _)| rule.specificity(self.src.as_ref()))
+            .fold((0, 0, 0), |acc, s| ((acc.0 + s.0), (acc.1 + s.1), (acc.2 + s.2)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn nth(src: &str) -> Option<Nth> {
+        Nth::parse(src, (0, src.len())).ok()
+    }
+    
+    // which of the first 10 positions it matches
+    fn positions(src: &str) -> Vec<i64> {
+        let nth = nth(src).unwrap();
+        (1..=10).filter(|idx| nth.matches(*idx)).collect()
+    }
+    
+    #[test]
+    fn an_plus_b() {
+        assert_eq!(positions("odd"), [1, 3, 5, 7, 9]);
+        assert_eq!(positions("EVEN"), [2, 4, 6, 8, 10]);
+        assert_eq!(positions("2n+1"), [1, 3, 5, 7, 9]);
+        assert_eq!(positions("3n"), [3, 6, 9]);
+        assert_eq!(positions("n"), (1..=10).collect::<Vec<_>>());
+        assert_eq!(positions("+n+8"), [8, 9, 10]);
+        assert_eq!(positions("n+0"), (1..=10).collect::<Vec<_>>());
+        assert_eq!(positions("4"), [4]);
+        assert_eq!(positions("+4"), [4]);
+        assert_eq!(positions(" 3n - 2 "), [1, 4, 7, 10]);
+        
+        // counting back from b
+        assert_eq!(positions("-n+3"), [1, 2, 3]);
+        assert_eq!(positions("-2n+5"), [1, 3, 5]);
+        
+        // nothing's at 0 or before
+        assert_eq!(positions("0"), Vec::<i64>::new());
+        assert_eq!(positions("-n"), Vec::<i64>::new());
+        assert_eq!(positions("0n+0"), Vec::<i64>::new());
+    }
+    
+    #[test]
+    fn invalid_an_plus_b() {
+        for src in ["", "n+", "2n1", "2n+b", "a", "1.5n", "--n", "2n+1n"] {
+            assert!(nth(src).is_none(), "{src}");
+        }
+    }
+    
+    #[test]
+    fn of_selector() {
+        let src = "2n+1 of .a, .b";
+        let nth = nth(src).unwrap();
+        assert_eq!((nth.a, nth.b), (2, 1));
+        assert_eq!(nth.of.map(|of| &src[of.0..of.1]), Some(".a, .b"));
+        
+        assert!(Selector::parse(":nth-child(2n of .a)".into()).is_ok());
+        assert!(Selector::parse(":nth-last-child(-n+2 of :is(.a, .b))".into()).is_ok());
+        // the `of-type` ones already narrow down the siblings
+        assert!(Selector::parse(":nth-of-type(2n of .a)".into()).is_err());
+    }
+    
+    #[test]
+    fn structural() {
+        let selector = Selector::parse("li:first-child:last-child:only-child:empty:root:nth-last-of-type(2)".into()).unwrap();
+        let (rule, _) = &selector.rules[0];
+        assert!(matches!(
+            rule.pseudo_classes.as_slice(),
+            [
+                PseudoClass::FirstChild,
+                PseudoClass::LastChild,
+                PseudoClass::OnlyChild,
+                PseudoClass::Empty,
+                PseudoClass::Root,
+                PseudoClass::NthLastOfType(Nth { a: 0, b: 2, of: None })
+            ]
+        ));
+    }
+    
+    fn specificity(src: &str) -> (usize, usize, usize) {
+        Selector::parse(src.into()).unwrap().specificity()
+    }
+    
+    #[test]
+    fn functional_specificity() {
+        // `:where()` never counts, `:is()`, `:not()` and `:has()` count as their most specific argument
+        assert_eq!(specificity(":where(#a, .b)"), (0, 0, 0));
+        assert_eq!(specificity("div:where(#a)"), (0, 0, 1));
+        assert_eq!(specificity(":is(#a, .b)"), (1, 0, 0));
+        assert_eq!(specificity("div:is(.a, span)"), (0, 1, 1));
+        assert_eq!(specificity(":not(.a.b, #c)"), (1, 0, 0));
+        assert_eq!(specificity(":has(> .a, >> #b)"), (1, 0, 0));
+        assert_eq!(specificity(":has(.a)"), (0, 1, 0));
+        assert_eq!(specificity(":is(:where(#a), .b)"), (0, 1, 0));
+        assert_eq!(specificity(":not(:is(#a, div))"), (1, 0, 0));
+        
+        // the `of S` counts on top of the pseudo-class itself
+        assert_eq!(specificity(":nth-child(2n of #a, .b)"), (1, 1, 0));
+        assert_eq!(specificity(":nth-child(2n)"), (0, 1, 0));
+        
+        assert_eq!(specificity("div > .a:hover"), (0, 2, 1));
+    }
+    
+    #[test]
+    fn functional() {
+        for src in [":not(.a, #b)", ":is(div, :not(.a))", ":has(> .a, + .b)", ":has(.a:is(.b))"] {
+            assert!(Selector::parse(src.into()).is_ok(), "{src}");
+        }
+        // every argument has to be a selector in its own right, or for `:has()` a link and a selector
+        assert!(Selector::parse(":where()".into()).is_err());
+        assert!(Selector::parse(":has(>)".into()).is_err());
+        assert!(Selector::parse(":is(.a, #)".into()).is_err());
+    }
+    
+    fn error(src: &str) -> (usize, String) {
+        let e = Selector::parse(src.into()).err().unwrap();
+        (e.offset, e.expected)
+    }
+    
+    #[test]
+    fn errors() {
+        assert_eq!(error(""), (0, "a selector".into()));
+        assert_eq!(error("div >"), (5, "a selector".into()));
+        assert_eq!(error("div p"), (4, "a link, like `>`, or the end of the selector".into()));
+        assert_eq!(error("%x"), (1, "an element's xid".into()));
+        assert_eq!(error("div#"), (4, "an id".into()));
+        assert_eq!(error(".a."), (3, "a class".into()));
+        assert_eq!(error("[a"), (2, "`]` or an operator like `=`".into()));
+        assert_eq!(error("[a!b]"), (3, "`=`".into()));
+        assert_eq!(error("[a=]"), (3, "a value".into()));
+        assert_eq!(error(":foo"), (1, "a pseudo-class".into()));
+        assert_eq!(error(":is(.a"), (6, "`)`".into()));
+        assert_eq!(error(":nth-child(zz)"), (11, "`an+b`, `odd` or `even`".into()));
+        
+        // offsets within arguments are from the start of the whole selector, in bytes
+        assert_eq!(error("div:is(.a, #)"), (12, "an id".into()));
+        assert_eq!(error("é > :not(.b, .)"), (15, "a class".into()));
+        assert_eq!(error(":has(> .a, >)"), (12, "a selector".into()));
+    }
+    
+    #[test]
+    fn multibyte() {
+        // whitespace and names that are more than a byte long
+        assert!(Selector::parse("a\u{3000}> b".into()).is_ok());
+        assert!(Selector::parse("é.ü > ñ".into()).is_ok());
+        assert_eq!(Selector::parse("a\u{3000}> b".into()).unwrap().rules.len(), 2);
+        
+        // a rule that starts with a multi-byte character, right where a link could be
+        assert!(Selector::parse(":has(é)".into()).is_ok());
+        assert!(Selector::parse(":has(> é)".into()).is_ok());
+        assert!(Selector::parse("p ü".into()).is_err());
+        assert!(Selector::parse("p\u{3000}ü".into()).is_err());
+    }
+    
+    fn attribute(src: &str) -> Attribute {
+        let mut selector = Selector::parse(src.into()).unwrap();
+        selector.rules.remove(0).0.attributes.remove(0)
+    }
+    
+    #[test]
+    fn attribute_values() {
+        assert_eq!(attribute("[title=\"a ] b\"]").value.as_deref(), Some("a ] b"));
+        assert_eq!(attribute("[title='it\\'s']").value.as_deref(), Some("it's"));
+        assert_eq!(attribute("[title=\"say \\\"hi\\\"\"]").value.as_deref(), Some("say \"hi\""));
+        assert_eq!(attribute("[title=\"\"]").value.as_deref(), Some(""));
+        assert_eq!(attribute("[ title = plain ]").value.as_deref(), Some("plain"));
+        assert_eq!(attribute("[title=a\\]b]").value.as_deref(), Some("a]b"));
+        
+        // up to 6 hex digits, with a space after them swallowed
+        assert_eq!(attribute("[title=\\31 23]").value.as_deref(), Some("123"));
+        assert_eq!(attribute("[title=\\5D]").value.as_deref(), Some("]"));
+        assert_eq!(attribute("[title=\"\\1F600\"]").value.as_deref(), Some("\u{1F600}"));
+        assert_eq!(attribute("[title=\"\\0\"]").value.as_deref(), Some("\u{FFFD}"));
+        assert_eq!(attribute("[title=\"\\110000\"]").value.as_deref(), Some("\u{FFFD}"));
+        
+        // the brackets and commas inside quotes are left alone
+        assert_eq!(split_list("[a=\",\"], :is([b=\")\"], c)"), [(0, "[a=\",\"]"), (9, ":is([b=\")\"], c)")]);
+        
+        assert!(Selector::parse("[title=\"open]".into()).is_err());
+        assert!(Selector::parse("[title=a b]".into()).is_err());
+        assert!(Selector::parse("[title=\"a\" x]".into()).is_err());
+    }
+    
+    #[test]
+    fn attribute_flags() {
+        assert!(attribute("[type=\"Submit\" i]").case_insensitive);
+        assert!(attribute("[type=Submit I]").case_insensitive);
+        assert!(!attribute("[type=\"Submit\" s]").case_insensitive);
+        assert!(!attribute("[type=\"Submit\"]").case_insensitive);
+        
+        assert!(attribute("[type=\"SUBMIT\" i]").matches("submit"));
+        assert!(!attribute("[type=\"SUBMIT\" s]").matches("submit"));
+        assert!(attribute("[type^=\"SUB\" i]").matches("submit"));
+    }
+    
+    #[test]
+    fn attribute_operators() {
+        let matches = |src: &str, value: &str| attribute(src).matches(value);
+        
+        assert!(matches("[a=x]", "x"));
+        assert!(!matches("[a=x]", "xy"));
+        assert!(matches("[a!=x]", "xy"));
+        assert!(matches("[a^=x]", "xy"));
+        assert!(matches("[a$=y]", "xy"));
+        assert!(matches("[a*=\"b c\"]", "ab cd"));
+        
+        // an empty value can't be the start, middle or end of anything
+        assert!(!matches("[a^=\"\"]", "x"));
+        assert!(!matches("[a*=\"\"]", "x"));
+        assert!(!matches("[a$=\"\"]", "x"));
+        
+        // one of the whitespace separated words
+        assert!(matches("[a~=b]", "a b  c"));
+        assert!(!matches("[a~=b]", "ab c"));
+        assert!(!matches("[a~=\"\"]", ""));
+        assert!(!matches("[a~=\"a b\"]", "a b"));
+        
+        // exactly, or followed by a dash
+        assert!(matches("[lang|=en]", "en"));
+        assert!(matches("[lang|=en]", "en-GB"));
+        assert!(!matches("[lang|=en]", "eng"));
+        assert!(!matches("[lang|=en]", "GB-en"));
+        
+        assert!(matches("[a]", "anything"));
+    }
+    
+    #[test]
+    fn list() {
+        assert_eq!(split_list(" a , :is(b, c),d "), [(1, "a"), (5, ":is(b, c)"), (15, "d")]);
+        assert_eq!(split_list(":nth-child(2n of .a, .b)"), [(0, ":nth-child(2n of .a, .b)")]);
+    }
 }
@@ -40,6 +40,15 @@ pub struct BorderColours {
     pub right: HexColour
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize)]
+pub enum Overflow {
+    #[default]
+    Visible,
+    Hidden,
+    Scroll
+}
+
 #[derive(Debug, Clone, Default, PartialEq)]
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct Transform {
@@ -56,6 +65,7 @@ pub struct Style {
     pub background: Background,
     pub text: Text,
     pub transform: Transform,
+    pub overflow: (Overflow, Overflow),
     pub transitions: std::collections::HashMap<Attribute, (Duration, Timing)>,
     
     pub border_radii: BorderRadii,
@@ -47,6 +47,9 @@ pub struct Text {
     pub colour: String
 }
 
+// in px, also what a line of scrolling amounts to
+pub(crate) const LINE_HEIGHT: f32 = 20.0;
+
 pub(crate) struct TextResources {
     pub(crate) font_system: cosmic_text::FontSystem,
     pub(crate) swash_cache: cosmic_text::SwashCache
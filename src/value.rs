@@ -1,5 +1,11 @@
 pub struct Value(serde_json::Value);
 
+impl Value {
+    pub(crate) fn json(&self) -> serde_json::Value {
+        self.0.clone()
+    }
+}
+
 use crate::wit::gen::exports::konall::heathen::value::{OwnValue, ValueTy};
 
 impl crate::wit::gen::exports::konall::heathen::value::GuestValue for Value {
@@ -1,6 +1,7 @@
 pub(crate) mod gen {
     pub use crate::instance::InstanceX;
     pub use crate::element::ElementX;
+    pub use crate::animations::AnimationX;
     pub use crate::value::Value;
 
     wit_bindgen::generate!({
@@ -8,6 +9,7 @@ pub(crate) mod gen {
         exports: {
             "konall:heathen/instance/instance": InstanceX,
             "konall:heathen/element/element": ElementX,
+            "konall:heathen/animation/animation": AnimationX,
             "konall:heathen/value/value": Value
         }
     });
@@ -17,11 +19,13 @@ pub(crate) mod types {
     pub(crate) use super::gen::konall::heathen::types::*;
     pub(crate) use super::gen::exports::konall::heathen::instance::OwnInstance as Instance;
     pub(crate) use super::gen::exports::konall::heathen::element::OwnElement as Element;
+    pub(crate) use super::gen::exports::konall::heathen::animation::OwnAnimation as Animation;
     pub(crate) use super::gen::exports::konall::heathen::value::OwnValue as Value;
 }
 
 pub(crate) mod traits {
     pub(crate) use super::gen::exports::konall::heathen::element::GuestElement;
+    pub(crate) use super::gen::exports::konall::heathen::animation::GuestAnimation;
     pub(crate) use super::gen::exports::konall::heathen::instance::GuestInstance;
     pub(crate) use super::gen::exports::konall::heathen::value::GuestValue;
 }